@@ -0,0 +1,36 @@
+//! Times method-heavy code on each backend, run with `cargo bench`
+
+use std::{
+    process::Command,
+    time::{Duration, Instant},
+};
+
+const RUNS: usize = 10;
+
+fn main() {
+    for backend in ["tree", "vm"] {
+        bench(backend);
+    }
+}
+
+fn bench(backend: &str) {
+    let script = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/methods.lox");
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+                .args(["--backend", backend])
+                .arg(script)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{script} failed");
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    println!(
+        "methods.lox on {backend}: median {:?}, min {:?} over {RUNS} runs",
+        times[RUNS / 2],
+        times[0]
+    );
+}
@@ -1,33 +1,281 @@
-use std::{
-    rc::Rc,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::{collections::HashMap, rc::Rc};
 
 use crate::{
+    interner::Interner,
     interpreter::Interpreter,
-    types::{Callable, Object},
+    io, json, process,
+    types::{Arity, Callable, Identifier, Object},
 };
 
-pub fn get_builtins() -> Vec<(&'static str, Object)> {
-    vec![("clock", clock_fn())]
+pub fn get_builtins(interner: &Interner) -> Vec<(&'static str, Object)> {
+    vec![
+        ("clock", Native::object("clock", Arity::exact(0), clock)),
+        ("random", Native::object("random", Arity::exact(0), random)),
+        (
+            "randomInt",
+            Native::object("randomInt", Arity::exact(2), random_int),
+        ),
+        ("list", list_fn()),
+        ("str", Object::Callable(Rc::new(StrFn))),
+        ("math", math_module(interner)),
+        ("json", json::module(interner)),
+    ]
+    .into_iter()
+    .chain(io::get_builtins())
+    .chain(process::get_builtins())
+    .collect()
 }
 
-fn clock_fn() -> Object {
-    Object::Callable(Rc::new(ClockFn {}))
+/// A named set of builtins, its members are accessed like properties
+#[derive(Debug)]
+pub struct Module {
+    pub(crate) name: Identifier,
+    members: HashMap<Identifier, Object>,
 }
 
-struct ClockFn;
-impl Callable for ClockFn {
-    fn arity(&self) -> usize {
-        0
+impl Module {
+    pub fn new(interner: &Interner, name: &str, members: Vec<(&str, Object)>) -> Self {
+        Self {
+            name: interner.intern(name),
+            members: members
+                .into_iter()
+                .map(|(name, member)| (interner.intern(name), member))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, name: &Identifier) -> Object {
+        self.members
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| panic!("module {} has no member '{name}'", self.name))
     }
+}
 
-    fn call(&self, _: &mut Interpreter, _: &[crate::types::Object]) -> Object {
-        let a = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        Object::Number(a.as_secs_f64())
+type NativeFunction = fn(&mut Interpreter, &[Object]) -> Object;
+
+/// A builtin implemented by a plain function
+pub struct Native {
+    name: &'static str,
+    arity: Arity,
+    function: NativeFunction,
+}
+
+impl Native {
+    pub fn object(name: &'static str, arity: Arity, function: NativeFunction) -> Object {
+        Object::Callable(Rc::new(Native {
+            name,
+            arity,
+            function,
+        }))
     }
+}
+
+impl Callable for Native {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: &[Object]) -> Object {
+        (self.function)(interpreter, args)
+    }
+
+    fn bind(&self, _instance: &Rc<crate::class::ClassInstance>) -> Object {
+        unimplemented!("can't bind {}", self.name)
+    }
+}
 
-    fn bind(&self, _instance: &crate::class::ClassInstance) -> Object {
-        unimplemented!("can't bind clock")
+/// Returns the `i`th argument of `function`, which must be a number
+pub fn number_arg(function: &str, args: &[Object], i: usize) -> f64 {
+    match args[i] {
+        Object::Number(n) => n,
+        ref other => panic!("{function}() expects a number argument, got '{other:?}'"),
     }
 }
+
+/// Returns the `i`th argument of `function`, which must be a string
+pub fn string_arg<'a>(function: &str, args: &'a [Object], i: usize) -> &'a Rc<str> {
+    match &args[i] {
+        Object::String(s) => s,
+        other => panic!("{function}() expects a string argument, got '{other:?}'"),
+    }
+}
+
+fn math_module(interner: &Interner) -> Object {
+    /// A math function of one number
+    macro_rules! unary {
+        ($name:literal, $f:expr) => {
+            Native::object($name, Arity::exact(1), |_, args| {
+                let f: fn(f64) -> f64 = $f;
+                Object::Number(f(number_arg(concat!("math.", $name), args, 0)))
+            })
+        };
+    }
+
+    Object::Module(Rc::new(Module::new(
+        interner,
+        "math",
+        vec![
+            ("PI", Object::Number(std::f64::consts::PI)),
+            ("E", Object::Number(std::f64::consts::E)),
+            ("INFINITY", Object::Number(f64::INFINITY)),
+            ("NAN", Object::Number(f64::NAN)),
+            ("floor", unary!("floor", f64::floor)),
+            ("ceil", unary!("ceil", f64::ceil)),
+            // Halfway cases round away from zero
+            ("round", unary!("round", f64::round)),
+            ("abs", unary!("abs", f64::abs)),
+            ("sqrt", unary!("sqrt", f64::sqrt)),
+            ("sin", unary!("sin", f64::sin)),
+            ("cos", unary!("cos", f64::cos)),
+            ("tan", unary!("tan", f64::tan)),
+            ("log", unary!("log", f64::ln)),
+            ("exp", unary!("exp", f64::exp)),
+            (
+                "pow",
+                Native::object("pow", Arity::exact(2), |_, args| {
+                    let base = number_arg("math.pow", args, 0);
+                    Object::Number(base.powf(number_arg("math.pow", args, 1)))
+                }),
+            ),
+            (
+                "min",
+                Native::object("min", Arity::at_least(1), |_, args| {
+                    math_fold("math.min", args, f64::min)
+                }),
+            ),
+            (
+                "max",
+                Native::object("max", Arity::at_least(1), |_, args| {
+                    math_fold("math.max", args, f64::max)
+                }),
+            ),
+            (
+                "isNaN",
+                Native::object("isNaN", Arity::exact(1), |_, args| {
+                    Object::Boolean(number_arg("math.isNaN", args, 0).is_nan())
+                }),
+            ),
+            (
+                "parseNumber",
+                Native::object("parseNumber", Arity::exact(1), parse_number),
+            ),
+            (
+                "toFixed",
+                Native::object("toFixed", Arity::exact(2), to_fixed),
+            ),
+        ],
+    )))
+}
+
+fn math_fold(function: &str, args: &[Object], f: fn(f64, f64) -> f64) -> Object {
+    let first = number_arg(function, args, 0);
+    let result = (1..args.len()).fold(first, |acc, i| f(acc, number_arg(function, args, i)));
+    Object::Number(result)
+}
+
+/// Parses a decimal number like `-1.5` or `2e3`, nil if the string isn't one
+fn parse_number(_: &mut Interpreter, args: &[Object]) -> Object {
+    let s = string_arg("math.parseNumber", args, 0).trim();
+    // Rust also accepts words like "inf" and "NaN", which aren't Lox numbers
+    let is_decimal = s.chars().any(|c| c.is_ascii_digit())
+        && s.chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+    match s.parse() {
+        Ok(n) if is_decimal => Object::Number(n),
+        _ => Object::Null,
+    }
+}
+
+/// Formats a number with a fixed number of decimals, `toFixed(3.14159, 2)` is "3.14"
+fn to_fixed(_: &mut Interpreter, args: &[Object]) -> Object {
+    let n = number_arg("math.toFixed", args, 0);
+    let digits = number_arg("math.toFixed", args, 1);
+    if digits.fract() != 0.0 || !(0.0..=100.0).contains(&digits) {
+        panic!("math.toFixed() digits must be an integer between 0 and 100, got {digits}");
+    }
+    Object::String(format!("{n:.*}", digits as usize).into())
+}
+
+/// Seconds since the Unix epoch, as told by the host's clock
+fn clock(interpreter: &mut Interpreter, _: &[Object]) -> Object {
+    Object::Number(interpreter.host().clock.now())
+}
+
+/// A random number in `0..1`
+fn random(interpreter: &mut Interpreter, _: &[Object]) -> Object {
+    Object::Number(interpreter.host_mut().rng.next_f64())
+}
+
+/// A random integer between `lo` and `hi`, both included
+fn random_int(interpreter: &mut Interpreter, args: &[Object]) -> Object {
+    let lo = number_arg("randomInt", args, 0);
+    let hi = number_arg("randomInt", args, 1);
+    // Larger ranges would have integers that can't be represented
+    let max_span = (1u64 << 53) as f64;
+    if lo.fract() != 0.0 || hi.fract() != 0.0 || lo > hi || hi - lo >= max_span {
+        panic!(
+            "randomInt() needs two integers with lo <= hi, at most 2^53 apart, got {lo} and {hi}"
+        );
+    }
+    let range = (hi - lo) as u64 + 1;
+    let offset = interpreter.host_mut().rng.below(range);
+    Object::Number(lo + offset as f64)
+}
+
+fn list_fn() -> Object {
+    Object::Callable(Rc::new(ListFn {}))
+}
+
+/// Builds a list out of all its arguments
+struct ListFn;
+impl Callable for ListFn {
+    fn arity(&self) -> Arity {
+        Arity::at_least(0)
+    }
+
+    fn call(&self, _: &mut Interpreter, args: &[Object]) -> Object {
+        Object::new_list(args.to_vec())
+    }
+
+    fn bind(&self, _instance: &Rc<crate::class::ClassInstance>) -> Object {
+        unimplemented!("can't bind list")
+    }
+}
+
+/// Converts any value to a string the same way `print` does
+struct StrFn;
+impl Callable for StrFn {
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: &[Object]) -> Object {
+        Object::String(interpreter.stringify(&args[0]).into())
+    }
+
+    fn bind(&self, _instance: &Rc<crate::class::ClassInstance>) -> Object {
+        unimplemented!("can't bind str")
+    }
+}
+
+#[test]
+fn test_parse_number() {
+    let mut interpreter = Interpreter::default();
+    let mut parse = |s: &str| parse_number(&mut interpreter, &[Object::String(s.into())]);
+    assert_eq!(parse("12"), Object::Number(12.0));
+    assert_eq!(parse(" -1.5e2 "), Object::Number(-150.0));
+    assert_eq!(parse("NaN"), Object::Null);
+    assert_eq!(parse("1.2.3"), Object::Null);
+    assert_eq!(parse(""), Object::Null);
+}
+
+#[test]
+#[should_panic(expected = "at most 2^53 apart")]
+fn test_random_int_span() {
+    let mut interpreter = Interpreter::default();
+    random_int(
+        &mut interpreter,
+        &[Object::Number(-1e300), Object::Number(1e300)],
+    );
+}
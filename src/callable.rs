@@ -1,72 +1,214 @@
 use std::rc::Rc;
 
 use crate::{
+    class::Class,
     environment::{EnvRef, Environment},
+    generator::{Generator, TreeCoroutine},
     interpreter::Interpreter,
     syntax::FunctionStmt,
-    types::{Callable, Identifier, Object},
+    types::{Arity, Callable, Identifier, Object},
 };
 
-pub type FunctionRef = Rc<Function>;
+/// A method of a class or trait, run by either backend
+pub type FunctionRef = Rc<dyn Callable>;
 
 #[derive(Debug, Clone)]
 pub struct Function {
-    decl: FunctionStmt,
+    decl: Rc<FunctionStmt>,
     closure: EnvRef,
     is_initializer: bool,
 }
 
 impl Function {
-    pub fn new(decl: FunctionStmt, closure: EnvRef, is_initializer: bool) -> Self {
+    pub fn new(decl: Rc<FunctionStmt>, closure: EnvRef, is_initializer: bool) -> Self {
         Self {
             decl,
             closure,
             is_initializer,
         }
     }
+
+    /// Whether calling the function returns a generator instead of running it
+    pub fn is_generator(&self) -> bool {
+        self.decl.is_generator
+    }
+
+    /// A new environment holding the arguments for the parameters
+    fn bind_args(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[Object],
+        named: &[(Identifier, Object)],
+    ) -> EnvRef {
+        let parameters = &self.decl.parameters;
+        for (name, _) in named {
+            if !parameters.iter().any(|p| &p.name == name) {
+                panic!("{} has no parameter named '{name}'", self.decl.identifier);
+            }
+        }
+
+        let env = Environment::new_ref(Some(self.closure.clone()));
+        for (i, param) in parameters.iter().enumerate() {
+            let name = &param.name;
+            let named_value = if named.is_empty() {
+                None
+            } else {
+                named
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+            };
+            let value = match (args.get(i).cloned(), named_value) {
+                (Some(_), Some(_)) => {
+                    panic!("argument '{name}' given both by position and by name")
+                }
+                (Some(value), None) | (None, Some(value)) => value,
+                // Defaults can refer to the parameters before them
+                (None, None) => match &param.default {
+                    Some(default) => interpreter.eval_in(default, env.clone()),
+                    None => panic!(
+                        "missing argument '{name}' in call to {}",
+                        self.decl.identifier
+                    ),
+                },
+            };
+            env.borrow_mut().define(value);
+        }
+
+        if self.decl.rest.is_some() {
+            let extra = args.get(parameters.len()..).unwrap_or_default();
+            env.borrow_mut().define(Object::new_list(extra.to_vec()));
+        }
+        env
+    }
+}
+
+/// A call in tail position, made by the function that's returning once it
+/// returned
+#[derive(Debug)]
+pub struct TailCall {
+    pub function: Function,
+    pub args: Vec<Object>,
+    pub named: Vec<(Identifier, Object)>,
 }
 
 impl Callable for Function {
-    fn arity(&self) -> usize {
-        self.decl.parameters.len()
+    fn arity(&self) -> Arity {
+        let parameters = &self.decl.parameters;
+        Arity {
+            min: parameters.iter().filter(|p| p.default.is_none()).count(),
+            max: self.decl.rest.is_none().then_some(parameters.len()),
+        }
     }
 
     fn call(&self, interpreter: &mut Interpreter, args: &[Object]) -> Object {
-        let env = Environment::new_ref(Some(self.closure.clone()));
-        for (i, param) in self.decl.parameters.iter().enumerate() {
-            env.borrow_mut()
-                .define(Identifier(param.lexeme.clone()), args[i].clone());
+        self.call_with_named(interpreter, args, &[])
+    }
+
+    fn call_with_named(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[Object],
+        named: &[(Identifier, Object)],
+    ) -> Object {
+        let mut env = self.bind_args(interpreter, args, named);
+        if self.decl.is_generator {
+            let coroutine = TreeCoroutine::new(self.decl.clone(), env);
+            return Object::Generator(Rc::new(Generator::new(
+                self.decl.identifier.clone(),
+                Box::new(coroutine),
+            )));
         }
 
-        // The "catch" statement
-        let ret_value = match interpreter.execute_block(&self.decl.body, env) {
-            Ok(x) => {
-                if self.is_initializer {
-                    self.closure.borrow().get_at(0, &"this".into())
-                } else {
-                    x
+        // Calls in tail position come back here instead of nesting, so
+        // tail recursion runs in constant stack space
+        let mut function = self.clone();
+        loop {
+            // The "catch" statement
+            match interpreter.execute_block(&function.decl.body, env) {
+                Ok(_) if function.is_initializer => {
+                    return function.closure.borrow().get_at(0, 0);
+                }
+                Ok(value) => return value,
+                Err(value) => {
+                    let Some(call) = interpreter.tail_call.take() else {
+                        return value;
+                    };
+                    env = call
+                        .function
+                        .bind_args(interpreter, &call.args, &call.named);
+                    function = call.function;
                 }
             }
-            Err(x) => return x,
-        };
-
-        if self.is_initializer {
-            self.closure.borrow().get_at(0, &"this".into())
-        } else {
-            ret_value
         }
     }
 
-    fn bind(&self, instance: &crate::class::ClassInstance) -> Object {
+    fn as_function(&self) -> Option<&Function> {
+        Some(self)
+    }
+
+    fn name(&self) -> Option<&Identifier> {
+        Some(&self.decl.identifier)
+    }
+
+    fn bind(&self, instance: &Rc<crate::class::ClassInstance>) -> Object {
         let env = Environment::new_ref(Some(self.closure.clone()));
-        env.borrow_mut().define(
-            Identifier("this".into()),
-            Object::ClassInstance(instance.clone().into()),
-        );
+        env.borrow_mut()
+            .define(Object::ClassInstance(instance.clone()));
         Object::Callable(Rc::new(Function::new(
             self.decl.clone(),
             env,
             self.is_initializer,
         )))
     }
+
+    fn bind_class(&self, class: Rc<Class>) -> Object {
+        let env = Environment::new_ref(Some(self.closure.clone()));
+        env.borrow_mut().define(Object::Class(class));
+        Object::Callable(Rc::new(Function::new(self.decl.clone(), env, false)))
+    }
+}
+
+#[test]
+fn test_tail_call_is_taken() {
+    let (ast, mut interpreter) = crate::resolver::resolve_source(
+        "
+        fun even(n) { if (n == 0) return true; return odd(n - 1); }
+        fun odd(n) { if (n == 0) return false; return even(n - 1); }
+        class Point { init(x) { this.x = x; } }
+        fun point(x) { return Point(x); }
+        fun native() { return clock(); }
+        fun numbers() { yield 1; }
+        fun generator() { return numbers(); }
+        ",
+    );
+    interpreter.interpret(ast);
+    let calls = [
+        (
+            "even",
+            vec![Object::Number(10.0)],
+            Some(Object::Boolean(true)),
+        ),
+        (
+            "odd",
+            vec![Object::Number(7.0)],
+            Some(Object::Boolean(true)),
+        ),
+        ("point", vec![Object::Number(1.0)], None),
+        ("native", vec![], None),
+        ("generator", vec![], None),
+    ];
+    for (name, args, expected) in calls {
+        let Object::Callable(function) =
+            interpreter.get_global(&interpreter.interner().intern(name))
+        else {
+            panic!("{name} isn't a function");
+        };
+        let result = function.call(&mut interpreter, &args);
+        if let Some(expected) = expected {
+            assert_eq!(result, expected, "{name}");
+        }
+        // A pending call left behind would be made by the next function to return
+        assert!(interpreter.tail_call.is_none(), "{name} left a tail call");
+    }
 }
@@ -0,0 +1,305 @@
+//! Bytecode for the `vm` backend: a chunk of instructions for each function,
+//! along with the constants they refer to.
+
+use std::{
+    fmt::{Debug, Display},
+    rc::Rc,
+};
+
+use crate::{
+    syntax::{FunctionKind, Pattern},
+    types::{Arity, Identifier},
+};
+
+/// Defines the opcodes, numbered in order so they can be decoded from a byte
+macro_rules! opcodes {
+    ($($(#[doc = $doc:literal])* $op:ident,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum OpCode {
+            $($(#[doc = $doc])* $op,)*
+        }
+
+        impl OpCode {
+            const ALL: &'static [OpCode] = &[$(OpCode::$op),*];
+
+            pub fn from_byte(byte: u8) -> Option<OpCode> {
+                Self::ALL.get(byte as usize).copied()
+            }
+        }
+    };
+}
+
+// Operands follow the opcode, `u16` operands are big endian
+opcodes! {
+    /// `u16` constant, pushes a number or string constant
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    Dup,
+    /// `u8` depth, pushes a copy of the value that many slots below the top
+    Peek,
+    /// `u8` count, removes that many values from under the top one
+    Squash,
+    /// `u8` slot in the current frame
+    GetLocal,
+    /// `u8` slot, leaves the value on the stack
+    SetLocal,
+    /// `u8` index into the closure's upvalues
+    GetUpvalue,
+    /// `u8` index, leaves the value on the stack
+    SetUpvalue,
+    /// Moves the local on top of the stack into the upvalues capturing it, and pops it
+    CloseUpvalue,
+    /// `u16` name constant
+    GetGlobal,
+    /// `u16` name constant, leaves the value on the stack
+    SetGlobal,
+    /// `u16` name constant, pops the value
+    DefineGlobal,
+    /// `u16` name constant
+    GetProperty,
+    /// `u16` name constant, `object value -> value`
+    SetProperty,
+    /// `u16` name constant, `this superclass -> method`
+    GetSuper,
+    /// `object index -> value`
+    GetIndex,
+    /// `object index value -> value`
+    SetIndex,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Negate,
+    Not,
+    Print,
+    /// `u16` offset forward
+    Jump,
+    /// `u16` offset forward, pops the condition, which must be a boolean
+    JumpIfFalse,
+    /// `u16` offset forward, keeps the value, for `and`
+    JumpIfFalsy,
+    /// `u16` offset forward, keeps the value, for `or`
+    JumpIfTruthy,
+    /// `u16` offset backward
+    Loop,
+    /// `u8` parameter and `u16` offset forward, skips a default value when the argument was given
+    JumpIfArg,
+    /// `u8` argument count
+    Call,
+    /// `u8` argument count, for `return f(x)`. Calls to closures replace the
+    /// current frame, others are made like `Call`, followed by a `Return`
+    TailCall,
+    /// `u8` positional argument count and `u16` constant with the names of the
+    /// arguments after them
+    CallNamed,
+    /// `u16` function constant, captures its upvalues
+    Closure,
+    Return,
+    /// `u16` class constant, pops the superclass, traits and methods it lists
+    Class,
+    /// `u16` trait constant, pops its methods
+    Trait,
+    /// `u16` enum constant
+    Enum,
+    /// `u16` item count
+    List,
+    /// `u16` constant with the keys, pops a value for each
+    Map,
+    /// `list item -> list`
+    Append,
+    /// `list items -> list`, for spreads
+    Extend,
+    /// Pops an iterable and starts iterating over it
+    Iter,
+    /// `u16` offset forward, pushes the next item or jumps once there are none
+    IterNext,
+    /// Drops the innermost iterator
+    IterEnd,
+    /// Suspends the current call, returning a generator that resumes it
+    Generator,
+    /// Suspends the current generator, handing the popped value to its caller
+    Yield,
+    /// `u16` pattern constant and `u16` offset forward. Pops the enum the
+    /// pattern names (nil for other patterns), then pushes the bindings if
+    /// the value under it matches or jumps otherwise
+    Match,
+    /// Fails with the value on top of the stack, which no arm matched
+    NoMatch,
+    /// `u8` item count and `u8` whether there is a rest target, checks the
+    /// list on top of the stack can be destructured
+    CheckList,
+    /// `u8` item count, replaces a list by a list of its items after that many
+    RestList,
+    /// `u16` name constant, replaces a map or object by the key or property
+    Field,
+}
+
+#[derive(Debug)]
+pub enum Constant {
+    Number(f64),
+    String(Rc<str>),
+    Name(Identifier),
+    /// Names of the named arguments of a call
+    Names(Vec<Identifier>),
+    /// Keys of a map literal
+    Keys(Vec<Rc<str>>),
+    Function(Rc<FunctionProto>),
+    Class(Rc<ClassProto>),
+    Trait(Rc<TraitProto>),
+    Enum(Rc<EnumProto>),
+    Pattern(Pattern),
+}
+
+impl Display for Constant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{n:?}"),
+            Constant::String(s) => write!(f, "{s:?}"),
+            Constant::Name(name) => write!(f, "{name}"),
+            Constant::Names(names) => {
+                let names: Vec<_> = names.iter().map(|n| n.to_string()).collect();
+                write!(f, "({})", names.join(", "))
+            }
+            Constant::Keys(keys) => write!(f, "{keys:?}"),
+            Constant::Function(function) => write!(f, "<fn {}>", function.name),
+            Constant::Class(class) => write!(f, "<class {}>", class.name),
+            Constant::Trait(mixin) => write!(f, "<trait {}>", mixin.name),
+            Constant::Enum(enumeration) => write!(f, "<enum {}>", enumeration.name),
+            Constant::Pattern(pattern) => write!(f, "{pattern}"),
+        }
+    }
+}
+
+/// A compiled function, closures over it are created at runtime
+#[derive(Debug)]
+pub struct FunctionProto {
+    pub name: Identifier,
+    pub parameters: Vec<Parameter>,
+    /// Whether the remaining positional arguments are collected in a list
+    pub rest: bool,
+    pub upvalues: Vec<UpvalueRef>,
+    pub chunk: Chunk,
+}
+
+impl FunctionProto {
+    pub fn arity(&self) -> Arity {
+        Arity {
+            min: self.parameters.iter().filter(|p| !p.has_default).count(),
+            max: (!self.rest).then_some(self.parameters.len()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Parameter {
+    pub name: Identifier,
+    pub has_default: bool,
+}
+
+/// Where a closure finds a captured variable when it's created: a local of
+/// the enclosing function, or one of the enclosing function's upvalues
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpvalueRef {
+    pub is_local: bool,
+    pub index: u8,
+}
+
+#[derive(Debug)]
+pub struct ClassProto {
+    pub name: Identifier,
+    pub has_superclass: bool,
+    pub traits: u8,
+    pub methods: Vec<(Identifier, FunctionKind)>,
+    pub static_methods: Vec<Identifier>,
+}
+
+#[derive(Debug)]
+pub struct TraitProto {
+    pub name: Identifier,
+    pub methods: Vec<(Identifier, FunctionKind)>,
+}
+
+#[derive(Debug)]
+pub struct EnumProto {
+    pub name: Identifier,
+    pub variants: Vec<(Identifier, Vec<Identifier>)>,
+}
+
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    /// The source line of the code from each offset on, in order
+    pub lines: Vec<(usize, usize)>,
+}
+
+impl Debug for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "<chunk of {} bytes, {} constants>",
+            self.code.len(),
+            self.constants.len()
+        )
+    }
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, line: usize) {
+        if self.lines.last().is_none_or(|&(_, last)| last != line) {
+            self.lines.push((self.code.len(), line));
+        }
+        self.code.push(byte);
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) -> u16 {
+        // Reuse names, numbers and strings so the same ones aren't stored twice
+        let existing = self.constants.iter().position(|c| match (c, &constant) {
+            (Constant::Name(a), Constant::Name(b)) => a == b,
+            (Constant::String(a), Constant::String(b)) => a == b,
+            (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        });
+        let index = existing.unwrap_or_else(|| {
+            self.constants.push(constant);
+            self.constants.len() - 1
+        });
+        u16::try_from(index).expect("too many constants in one function")
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// The source line of the instruction at `offset`
+    pub fn line(&self, offset: usize) -> usize {
+        let i = self.lines.partition_point(|&(start, _)| start <= offset);
+        i.checked_sub(1).map_or(0, |i| self.lines[i].1)
+    }
+}
+
+#[test]
+fn test_lines() {
+    let mut chunk = Chunk::default();
+    chunk.write(OpCode::Nil as u8, 1);
+    chunk.write(OpCode::Pop as u8, 1);
+    chunk.write(OpCode::True as u8, 3);
+    assert_eq!(chunk.line(1), 1);
+    assert_eq!(chunk.line(2), 3);
+}
+
+#[test]
+fn test_opcodes() {
+    assert_eq!(OpCode::from_byte(OpCode::Field as u8), Some(OpCode::Field));
+    assert_eq!(OpCode::from_byte(u8::MAX), None);
+}
@@ -2,24 +2,43 @@ use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use crate::{
     callable::FunctionRef,
-    types::{Callable, Identifier, Object},
+    interpreter::Interpreter,
+    syntax::FunctionKind,
+    types::{Arity, Identifier, Object},
 };
 
 type Methods = HashMap<Identifier, FunctionRef>;
 
-#[derive(Debug, Clone)]
 pub struct Class {
     pub(crate) name: Identifier,
     pub(crate) methods: Methods,
+    /// Computed properties, invoked on property access and assignment
+    pub(crate) getters: Methods,
+    pub(crate) setters: Methods,
     pub(crate) superclass: Option<Rc<Class>>,
+    /// Methods and fields that belong to the class itself rather than to its
+    /// instances, the class acts as the (only) instance of its metaclass.
+    pub(crate) static_methods: Methods,
+    pub(crate) fields: ClassInstanceState,
 }
 
 impl Class {
-    pub fn new(name: Identifier, superclass: Option<Rc<Class>>, methods: Methods) -> Self {
+    pub fn new(
+        name: Identifier,
+        superclass: Option<Rc<Class>>,
+        methods: Methods,
+        getters: Methods,
+        setters: Methods,
+        static_methods: Methods,
+    ) -> Self {
         Self {
             name,
             superclass,
             methods,
+            getters,
+            setters,
+            static_methods,
+            fields: Default::default(),
         }
     }
 
@@ -27,29 +46,79 @@ impl Class {
         self.methods
             .get(name)
             .cloned()
-            .map(|o| Object::Callable(o))
+            .map(Object::Callable)
             .or_else(|| self.superclass.as_ref().and_then(|s| s.find_method(name)))
     }
+
+    pub fn find_static_method(&self, name: &Identifier) -> Option<FunctionRef> {
+        self.find_in(name, |class| &class.static_methods)
+    }
+
+    pub fn find_getter(&self, name: &Identifier) -> Option<FunctionRef> {
+        self.find_in(name, |class| &class.getters)
+    }
+
+    pub fn find_setter(&self, name: &Identifier) -> Option<FunctionRef> {
+        self.find_in(name, |class| &class.setters)
+    }
+
+    /// Looks up `name` in the given method table of this class and its superclasses
+    fn find_in(&self, name: &Identifier, table: fn(&Class) -> &Methods) -> Option<FunctionRef> {
+        table(self).get(name).cloned().or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|s| s.find_in(name, table))
+        })
+    }
+
+    fn find_field(&self, name: &Identifier) -> Option<Object> {
+        self.fields
+            .borrow()
+            .get(name)
+            .cloned()
+            .or_else(|| self.superclass.as_ref().and_then(|s| s.find_field(name)))
+    }
+
+    /// Looks up a class-level property. Static methods are bound to `class`
+    /// so that `this` refers to the class it was accessed through.
+    pub fn get(class: &Rc<Class>, name: &Identifier) -> Object {
+        if let Some(field) = class.find_field(name) {
+            return field;
+        }
+
+        if let Some(method) = class.find_static_method(name) {
+            return method.bind_class(class.clone());
+        }
+
+        panic!("Undefined static property '{name}' on class {}", class.name)
+    }
+
+    pub fn set(&self, name: Identifier, value: Object) {
+        self.fields.borrow_mut().insert(name, value);
+    }
 }
 
-impl Callable for Class {
-    fn arity(&self) -> usize {
-        let initializer = self.find_method(&"init".into());
+impl Class {
+    pub fn arity(&self) -> Arity {
+        let initializer = self.find_method(&Identifier::INIT);
         match initializer {
             Some(Object::Callable(t)) => t.arity(),
-            None => 0,
+            None => Arity::exact(0),
             Some(e) => panic!("init method must be a callable, got {e} instead"),
         }
     }
 
-    fn call(
-        &self,
-        interpreter: &mut crate::interpreter::Interpreter,
-        args: &[crate::types::Object],
-    ) -> crate::types::Object {
-        let instance = ClassInstance::new(self.clone());
+    /// Creates an instance of `class` and runs its initializer. Instances
+    /// share the class, so this costs the same however many methods it has.
+    pub fn instantiate(
+        class: &Rc<Class>,
+        interpreter: &mut Interpreter,
+        args: &[Object],
+        named: &[(Identifier, Object)],
+    ) -> Object {
+        let instance = Rc::new(ClassInstance::new(class.clone()));
 
-        let initializer = self.find_method(&"init".into());
+        let initializer = class.find_method(&Identifier::INIT);
         if let Some(initializer) = initializer {
             let Object::Callable(initializer) = initializer else {
                 panic!("initializer must be a callable function");
@@ -59,46 +128,166 @@ impl Callable for Class {
                 panic!("initializer->bind did not return a callable, this is a bug");
             };
 
-            initializer.call(interpreter, args);
+            initializer.call_with_named(interpreter, args, named);
         }
 
-        Object::ClassInstance(instance.into())
+        Object::ClassInstance(instance)
     }
+}
 
-    fn bind(&self, _instance: &ClassInstance) -> Object {
-        unimplemented!("can't call bind on a class")
+/// A named set of methods that can be mixed into otherwise unrelated classes
+pub struct Trait {
+    pub(crate) name: Identifier,
+    pub(crate) methods: Methods,
+    pub(crate) getters: Methods,
+    pub(crate) setters: Methods,
+}
+
+impl Trait {
+    pub fn new(name: Identifier) -> Self {
+        Self {
+            name,
+            methods: Default::default(),
+            getters: Default::default(),
+            setters: Default::default(),
+        }
+    }
+}
+
+/// The methods a class gets from its traits. Two traits can't give it the
+/// same one unless the class overrides it. The resolver checks this for
+/// traits it knows by name, this catches the rest when the class is made.
+#[derive(Default)]
+pub(crate) struct Mixins {
+    pub(crate) methods: Methods,
+    pub(crate) getters: Methods,
+    pub(crate) setters: Methods,
+    /// The trait each method came from
+    provided_by: HashMap<(Identifier, FunctionKind), Identifier>,
+}
+
+impl Mixins {
+    pub(crate) fn add(
+        &mut self,
+        class: &Identifier,
+        mixin: &Trait,
+        overridden: impl Fn(&Identifier, FunctionKind) -> bool,
+    ) {
+        let tables = [
+            (FunctionKind::Function, &mixin.methods, &mut self.methods),
+            (FunctionKind::Getter, &mixin.getters, &mut self.getters),
+            (FunctionKind::Setter, &mixin.setters, &mut self.setters),
+        ];
+        for (kind, members, table) in tables {
+            for (name, method) in members {
+                if overridden(name, kind) {
+                    continue;
+                }
+                let provider = mixin.name.clone();
+                if let Some(other) = self.provided_by.insert((name.clone(), kind), provider) {
+                    panic!(
+                        "class '{class}' gets conflicting '{name}' from traits '{other}' and '{}', override it in the class",
+                        mixin.name
+                    );
+                }
+                table.insert(name.clone(), method.clone());
+            }
+        }
     }
 }
 
 pub type ClassInstanceState = Rc<RefCell<HashMap<Identifier, Object>>>;
 
-#[derive(Clone)]
 pub struct ClassInstance {
-    pub(crate) class: Class,
+    pub(crate) class: Rc<Class>,
     pub(crate) fields: ClassInstanceState,
 }
 
 impl ClassInstance {
-    pub fn new(class: Class) -> Self {
+    pub fn new(class: Rc<Class>) -> Self {
         Self {
             class,
             fields: Default::default(),
         }
     }
 
-    pub fn get(&self, name: &Identifier) -> Object {
+    pub fn get(self: &Rc<Self>, interpreter: &mut Interpreter, name: &Identifier) -> Object {
         if let Some(field) = self.fields.borrow().get(name).cloned() {
             return field;
         }
 
+        if *name == Identifier::CLASS {
+            return Object::Class(self.class.clone());
+        }
+
+        if let Some(getter) = self.class.find_getter(name) {
+            let Object::Callable(getter) = getter.bind(self) else {
+                panic!("getter->bind did not return a callable, this is a bug");
+            };
+            return getter.call(interpreter, &[]);
+        }
+
         if let Some(Object::Callable(method)) = self.class.find_method(name) {
-            return method.bind(&self);
+            return method.bind(self);
         }
 
         panic!("Undefined property '{name}'")
     }
 
-    pub fn set(&self, name: Identifier, value: Object) {
+    pub fn set(self: &Rc<Self>, interpreter: &mut Interpreter, name: Identifier, value: Object) {
+        if let Some(setter) = self.class.find_setter(&name) {
+            let Object::Callable(setter) = setter.bind(self) else {
+                panic!("setter->bind did not return a callable, this is a bug");
+            };
+            setter.call(interpreter, &[value]);
+            return;
+        }
+
         self.fields.borrow_mut().insert(name, value);
     }
 }
+
+#[test]
+fn test_instances_share_class() {
+    let mut interpreter = Interpreter::default();
+    let class = Rc::new(Class::new(
+        interpreter.interner().intern("Foo"),
+        None,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    ));
+    let Object::ClassInstance(a) = Class::instantiate(&class, &mut interpreter, &[], &[]) else {
+        panic!("expected an instance");
+    };
+    let Object::ClassInstance(b) = Class::instantiate(&class, &mut interpreter, &[], &[]) else {
+        panic!("expected an instance");
+    };
+    assert!(Rc::ptr_eq(&a.class, &class));
+    assert!(Rc::ptr_eq(&a.class, &b.class));
+    assert!(!Rc::ptr_eq(&a, &b));
+}
+
+/// Traits under another name get past the resolver's check
+#[cfg(test)]
+const ALIASED_TRAITS: &str = "
+    trait Loud { greet() { return 1; } }
+    trait Quiet { greet() { return 2; } }
+    var Hushed = Quiet;
+    class Speaker with Loud, Hushed {}
+";
+
+#[test]
+#[should_panic(expected = "conflicting 'greet' from traits 'Loud' and 'Quiet'")]
+fn test_aliased_trait_conflict() {
+    let (ast, mut interpreter) = crate::resolver::resolve_source(ALIASED_TRAITS);
+    interpreter.interpret(ast);
+}
+
+#[test]
+#[should_panic(expected = "conflicting 'greet' from traits 'Loud' and 'Quiet'")]
+fn test_aliased_trait_conflict_vm() {
+    let (ast, mut interpreter) = crate::resolver::resolve_source(ALIASED_TRAITS);
+    crate::vm::interpret(&mut interpreter, crate::compiler::compile(&ast));
+}
@@ -0,0 +1,1048 @@
+//! Compiles a program to bytecode for the `vm` backend.
+//!
+//! Locals live in the stack slots of their call frame and closures capture
+//! them as upvalues, as in clox. The compiler tracks how deep the stack is
+//! after every instruction, so locals declared in the middle of an expression
+//! (the bindings of a match arm, the list being destructured) get the right
+//! slot too.
+
+use std::rc::Rc;
+
+use crate::{
+    chunk::{
+        Chunk, ClassProto, Constant, EnumProto, FunctionProto, OpCode, Parameter, TraitProto,
+        UpvalueRef,
+    },
+    scanner::{Token, TokenType},
+    syntax::{
+        Binding, ClassDecl, Declaration, Expr, ExprKind, FunctionKind, FunctionStmt, MatchArm,
+        Pattern, Program, Stmt,
+    },
+    types::{Identifier, Object},
+};
+
+pub fn compile(program: &Program) -> Rc<FunctionProto> {
+    let mut compiler = Compiler {
+        functions: Vec::new(),
+        line: 0,
+    };
+    compiler.begin_function(Identifier::SCRIPT, FunctionType::Script);
+    let Program::Declarations(decls) = program;
+    for decl in decls {
+        compiler.declaration(decl);
+    }
+    compiler.emit(OpCode::Nil);
+    compiler.emit(OpCode::Return);
+    Rc::new(compiler.end_function())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    Script,
+    Function,
+    /// Methods of classes and traits, and static methods, `this` is in slot 0
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: Identifier,
+    scope: usize,
+    slot: u8,
+    captured: bool,
+}
+
+/// A function being compiled, the innermost one is last
+struct FunctionState {
+    proto: FunctionProto,
+    locals: Vec<Local>,
+    scope: usize,
+    /// Number of values on the stack of the frame, locals included
+    depth: usize,
+    /// Whether `return f(x)` can reuse the frame, not in the script, in
+    /// initializers or in generators
+    tail_calls: bool,
+}
+
+struct Compiler {
+    functions: Vec<FunctionState>,
+    /// The line of the last token seen, for error messages
+    line: usize,
+}
+
+/// Locals the compiler adds for itself, they can't be named in the source
+fn hidden() -> Identifier {
+    Identifier::HIDDEN
+}
+
+/// The net number of values an instruction pushes. Instructions that pop a
+/// number of values given by their operands return 0, the compiler adjusts
+/// the depth itself after emitting them.
+fn stack_effect(op: OpCode) -> isize {
+    use OpCode::*;
+    match op {
+        Constant | Nil | True | False | Dup | Peek | GetLocal | GetUpvalue | GetGlobal
+        | Closure | Enum | IterNext => 1,
+        Pop | CloseUpvalue | DefineGlobal | SetProperty | GetSuper | GetIndex | Equal
+        | NotEqual | Greater | GreaterEqual | Less | LessEqual | Add | Subtract | Multiply
+        | Divide | Print | JumpIfFalse | Return | Append | Extend | Iter | Yield | Match => -1,
+        SetIndex => -2,
+        SetLocal | SetUpvalue | SetGlobal | GetProperty | Negate | Not | Jump | JumpIfFalsy
+        | JumpIfTruthy | Loop | JumpIfArg | IterEnd | Generator | NoMatch | CheckList
+        | RestList | Field => 0,
+        Squash | Call | TailCall | CallNamed | Class | Trait | List | Map => 0,
+    }
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("bug: no function being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().proto.chunk
+    }
+
+    fn begin_function(&mut self, name: Identifier, kind: FunctionType) {
+        // Slot 0 holds `this` in methods, and nothing that can be named otherwise
+        let receiver = match kind {
+            FunctionType::Method | FunctionType::Initializer => Identifier::THIS,
+            FunctionType::Script | FunctionType::Function => hidden(),
+        };
+        self.functions.push(FunctionState {
+            proto: FunctionProto {
+                name,
+                parameters: Vec::new(),
+                rest: false,
+                upvalues: Vec::new(),
+                chunk: Chunk::default(),
+            },
+            locals: vec![Local {
+                name: receiver,
+                scope: 0,
+                slot: 0,
+                captured: false,
+            }],
+            scope: 0,
+            depth: 1,
+            tail_calls: false,
+        });
+    }
+
+    fn end_function(&mut self) -> FunctionProto {
+        self.functions
+            .pop()
+            .expect("bug: no function being compiled")
+            .proto
+    }
+
+    fn adjust_depth(&mut self, effect: isize) {
+        let state = self.current();
+        state.depth = state
+            .depth
+            .checked_add_signed(effect)
+            .expect("bug: stack depth went negative");
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.chunk().write(byte, line);
+    }
+
+    fn emit(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+        self.adjust_depth(stack_effect(op));
+    }
+
+    fn emit_u8(&mut self, op: OpCode, operand: u8) {
+        self.emit(op);
+        self.emit_byte(operand);
+    }
+
+    fn emit_u16(&mut self, op: OpCode, operand: u16) {
+        self.emit(op);
+        for byte in operand.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    fn make_constant(&mut self, constant: Constant) -> u16 {
+        self.chunk().add_constant(constant)
+    }
+
+    fn name_constant(&mut self, name: &Identifier) -> u16 {
+        self.make_constant(Constant::Name(name.clone()))
+    }
+
+    /// Emits a jump with a placeholder offset, returns where to patch it
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_u16(op, u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    /// Points the jump at `offset` to the next instruction
+    fn patch_jump(&mut self, offset: usize) {
+        let jump = self.chunk().code.len() - offset - 2;
+        let jump = u16::try_from(jump).expect("too much code to jump over");
+        self.chunk().code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, start: usize) {
+        let offset = self.chunk().code.len() + 3 - start;
+        let offset = u16::try_from(offset).expect("loop body too large");
+        self.emit_u16(OpCode::Loop, offset);
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope += 1;
+    }
+
+    /// Pops the locals of the scope, closing the ones captured by closures
+    fn end_scope(&mut self) {
+        self.current().scope -= 1;
+        let scope = self.current().scope;
+        while let Some(local) = self.current().locals.last() {
+            if local.scope <= scope {
+                break;
+            }
+            let op = if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit(op);
+            self.current().locals.pop();
+        }
+    }
+
+    /// Forgets the locals of a scope whose end can't be reached, or whose
+    /// values the instructions already removed
+    fn discard_scope(&mut self) {
+        let state = self.current();
+        state.scope -= 1;
+        let scope = state.scope;
+        while state.locals.last().is_some_and(|local| local.scope > scope) {
+            state.locals.pop();
+        }
+    }
+
+    /// Declares a local in the given slot of the current frame
+    fn add_local_at(&mut self, name: Identifier, slot: usize) -> u8 {
+        let state = self.current();
+        let slot = u8::try_from(slot).expect("too many local variables in function");
+        state.locals.push(Local {
+            name,
+            scope: state.scope,
+            slot,
+            captured: false,
+        });
+        slot
+    }
+
+    /// Declares the value on top of the stack as a local
+    fn add_local(&mut self, name: Identifier) -> u8 {
+        let slot = self.current().depth - 1;
+        self.add_local_at(name, slot)
+    }
+
+    /// Defines the value on top of the stack as a variable of the current scope
+    fn define_variable(&mut self, name: &Identifier) {
+        if self.current().scope == 0 {
+            let name = self.name_constant(name);
+            self.emit_u16(OpCode::DefineGlobal, name);
+        } else {
+            self.add_local(name.clone());
+        }
+    }
+
+    fn resolve_local(&self, level: usize, name: &Identifier) -> Option<usize> {
+        self.functions[level]
+            .locals
+            .iter()
+            .rposition(|local| &local.name == name)
+    }
+
+    /// Finds `name` in the functions enclosing the one at `level`, capturing
+    /// it in each function in between
+    fn resolve_upvalue(&mut self, level: usize, name: &Identifier) -> Option<u8> {
+        let enclosing = level.checked_sub(1)?;
+        if let Some(local) = self.resolve_local(enclosing, name) {
+            let local = &mut self.functions[enclosing].locals[local];
+            local.captured = true;
+            let slot = local.slot;
+            return Some(self.add_upvalue(level, true, slot));
+        }
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(level, false, index))
+    }
+
+    fn add_upvalue(&mut self, level: usize, is_local: bool, index: u8) -> u8 {
+        let upvalues = &mut self.functions[level].proto.upvalues;
+        let upvalue = UpvalueRef { is_local, index };
+        let i = upvalues
+            .iter()
+            .position(|u| *u == upvalue)
+            .unwrap_or_else(|| {
+                upvalues.push(upvalue);
+                upvalues.len() - 1
+            });
+        u8::try_from(i).expect("too many captured variables in function")
+    }
+
+    /// Reads a variable, or assigns the value on top of the stack to it
+    fn variable(&mut self, name: &Identifier, assign: bool) {
+        let level = self.functions.len() - 1;
+        if let Some(local) = self.resolve_local(level, name) {
+            let slot = self.functions[level].locals[local].slot;
+            let op = if assign {
+                OpCode::SetLocal
+            } else {
+                OpCode::GetLocal
+            };
+            self.emit_u8(op, slot);
+        } else if let Some(index) = self.resolve_upvalue(level, name) {
+            let op = if assign {
+                OpCode::SetUpvalue
+            } else {
+                OpCode::GetUpvalue
+            };
+            self.emit_u8(op, index);
+        } else {
+            let name = self.name_constant(name);
+            let op = if assign {
+                OpCode::SetGlobal
+            } else {
+                OpCode::GetGlobal
+            };
+            self.emit_u16(op, name);
+        }
+    }
+
+    fn declaration(&mut self, decl: &Declaration) {
+        match decl {
+            Declaration::Statement(stmt) => self.statement(stmt),
+            Declaration::Var {
+                binding,
+                expression,
+            } => {
+                self.expression(expression);
+                self.bind(binding);
+            }
+        }
+    }
+
+    /// Defines the variables of `binding` from the value on top of the stack
+    fn bind(&mut self, binding: &Binding) {
+        match binding {
+            Binding::Name(name) => self.define_variable(name),
+            Binding::List { items, rest } => {
+                let n_items = u8::try_from(items.len()).expect("too many items to destructure");
+                self.emit_u8(OpCode::CheckList, n_items);
+                self.emit_byte(rest.is_some() as u8);
+                let list = self.hold_destructured();
+                for (i, item) in items.iter().enumerate() {
+                    self.emit_u8(OpCode::GetLocal, list);
+                    let index = self.make_constant(Constant::Number(i as f64));
+                    self.emit_u16(OpCode::Constant, index);
+                    self.emit(OpCode::GetIndex);
+                    self.bind(item);
+                }
+                if let Some(rest) = rest {
+                    self.emit_u8(OpCode::GetLocal, list);
+                    self.emit_u8(OpCode::RestList, n_items);
+                    self.define_variable(rest);
+                }
+                self.release_destructured();
+            }
+            Binding::Map { entries } => {
+                let value = self.hold_destructured();
+                for (key, binding) in entries {
+                    self.emit_u8(OpCode::GetLocal, value);
+                    let key = self.name_constant(key);
+                    self.emit_u16(OpCode::Field, key);
+                    self.bind(binding);
+                }
+                self.release_destructured();
+            }
+        }
+    }
+
+    /// Keeps the value being destructured in a hidden local while its parts
+    /// are bound. Inside a scope it stays there until the scope ends.
+    fn hold_destructured(&mut self) -> u8 {
+        self.add_local(hidden())
+    }
+
+    fn release_destructured(&mut self) {
+        // Parts bound at the top level are globals, which don't take stack
+        // slots, so the value can go as soon as they're defined
+        if self.current().scope == 0 {
+            self.current().locals.pop();
+            self.emit(OpCode::Pop);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Pop);
+            }
+            Stmt::Print(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Print);
+            }
+            Stmt::FunctionDecl(decl) => {
+                if self.current().scope == 0 {
+                    self.function(decl, FunctionType::Function);
+                    self.define_variable(&decl.identifier);
+                } else {
+                    // Declared before the body so it can call itself
+                    let slot = self.current().depth;
+                    self.add_local_at(decl.identifier.clone(), slot);
+                    self.function(decl, FunctionType::Function);
+                }
+            }
+            Stmt::ClassDecl(decl) => self.class_declaration(decl),
+            Stmt::TraitDecl(decl) => {
+                let mut methods = Vec::new();
+                for method in &decl.methods {
+                    self.function(method, FunctionType::Method);
+                    methods.push((method.identifier.clone(), method.kind));
+                }
+                self.adjust_depth(1 - methods.len() as isize);
+                let proto = self.make_constant(Constant::Trait(Rc::new(TraitProto {
+                    name: decl.name.clone(),
+                    methods,
+                })));
+                self.emit_u16(OpCode::Trait, proto);
+                self.define_variable(&decl.name);
+            }
+            Stmt::EnumDecl(decl) => {
+                let proto = self.make_constant(Constant::Enum(Rc::new(EnumProto {
+                    name: decl.name.clone(),
+                    variants: decl.variants.clone(),
+                })));
+                self.emit_u16(OpCode::Enum, proto);
+                self.define_variable(&decl.name);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.statement(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let end_jump = self.emit_jump(OpCode::Jump);
+                        self.patch_jump(else_jump);
+                        self.statement(else_branch);
+                        self.patch_jump(end_jump);
+                    }
+                    None => self.patch_jump(else_jump),
+                }
+            }
+            Stmt::Return { value } => {
+                match &value.kind {
+                    ExprKind::Call {
+                        callee,
+                        parens,
+                        args,
+                        named_args,
+                    } if self.current().tail_calls => {
+                        self.call(callee, parens, args, named_args, true);
+                    }
+                    _ => self.expression(value),
+                }
+                self.emit(OpCode::Return);
+            }
+            Stmt::Yield { keyword, value } => {
+                self.expression(value);
+                self.line = keyword.line;
+                self.emit(OpCode::Yield);
+            }
+            Stmt::While { condition, body } => {
+                let start = self.chunk().code.len();
+                self.expression(condition);
+                let exit = self.emit_jump(OpCode::JumpIfFalse);
+                self.statement(body);
+                self.emit_loop(start);
+                self.patch_jump(exit);
+            }
+            Stmt::ForIn {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                self.emit(OpCode::Iter);
+                let start = self.chunk().code.len();
+                let exit = self.emit_jump(OpCode::IterNext);
+                // A fresh scope for each item, so closures capture that item
+                self.begin_scope();
+                self.bind(binding);
+                self.statement(body);
+                self.end_scope();
+                self.emit_loop(start);
+                self.patch_jump(exit);
+                self.emit(OpCode::IterEnd);
+            }
+            Stmt::Block(decls) => {
+                self.begin_scope();
+                for decl in decls {
+                    self.declaration(decl);
+                }
+                self.end_scope();
+            }
+            Stmt::Match {
+                keyword,
+                value,
+                arms,
+            } => {
+                self.line = keyword.line;
+                self.match_statement(value, arms, false);
+            }
+        }
+    }
+
+    /// Compiles a function and emits the closure over it
+    fn function(&mut self, decl: &FunctionStmt, kind: FunctionType) {
+        self.begin_function(decl.identifier.clone(), kind);
+        self.begin_scope();
+        // The arguments are already on the stack, but each parameter is only
+        // named once its default is compiled, like the resolver declares them
+        let first = self.current().locals.len();
+        for param in &decl.parameters {
+            self.line = param.line;
+            self.adjust_depth(1);
+            self.add_local(hidden());
+            self.current().proto.parameters.push(Parameter {
+                name: param.name.clone(),
+                has_default: param.default.is_some(),
+            });
+        }
+        if decl.rest.is_some() {
+            self.adjust_depth(1);
+            self.add_local(hidden());
+            self.current().proto.rest = true;
+        }
+
+        // Defaults can refer to the parameters before them
+        for (i, param) in decl.parameters.iter().enumerate() {
+            if let Some(default) = &param.default {
+                let slot = u8::try_from(i).expect("too many parameters");
+                self.emit_u8(OpCode::JumpIfArg, slot);
+                self.emit_byte(0xff);
+                self.emit_byte(0xff);
+                let skip = self.chunk().code.len() - 2;
+                self.expression(default);
+                self.emit_u8(OpCode::SetLocal, slot + 1);
+                self.emit(OpCode::Pop);
+                self.patch_jump(skip);
+            }
+            self.current().locals[first + i].name = param.name.clone();
+        }
+        if let Some(rest) = &decl.rest {
+            let rest_local = first + decl.parameters.len();
+            self.current().locals[rest_local].name = rest.clone();
+        }
+        if decl.is_generator {
+            self.emit(OpCode::Generator);
+        }
+        self.current().tail_calls = kind != FunctionType::Initializer && !decl.is_generator;
+        let header_line = self.line;
+
+        // A function without `return` returns the value of its last statement
+        match decl.body.split_last() {
+            Some((last, body)) if kind != FunctionType::Initializer && !decl.is_generator => {
+                for decl in body {
+                    self.declaration(decl);
+                }
+                self.tail(last);
+            }
+            _ => {
+                for decl in &decl.body {
+                    self.declaration(decl);
+                }
+                if kind == FunctionType::Initializer {
+                    self.emit_u8(OpCode::GetLocal, 0);
+                } else {
+                    self.emit(OpCode::Nil);
+                }
+                self.emit(OpCode::Return);
+            }
+        }
+
+        let proto = self.end_function();
+        self.line = header_line;
+        let proto = self.make_constant(Constant::Function(Rc::new(proto)));
+        self.emit_u16(OpCode::Closure, proto);
+    }
+
+    /// Compiles the last declaration of a function body, returning its value
+    fn tail(&mut self, decl: &Declaration) {
+        match decl {
+            Declaration::Statement(stmt) => self.tail_statement(stmt),
+            Declaration::Var { .. } => {
+                self.declaration(decl);
+                self.emit(OpCode::Nil);
+                self.emit(OpCode::Return);
+            }
+        }
+    }
+
+    fn tail_statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Return);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                let depth = self.current().depth;
+                self.tail_statement(then_branch);
+                self.current().depth = depth;
+                self.patch_jump(else_jump);
+                match else_branch {
+                    Some(else_branch) => self.tail_statement(else_branch),
+                    None => {
+                        self.emit(OpCode::Nil);
+                        self.emit(OpCode::Return);
+                    }
+                }
+                self.current().depth = depth;
+            }
+            Stmt::Block(decls) => {
+                let depth = self.current().depth;
+                self.begin_scope();
+                match decls.split_last() {
+                    Some((last, decls)) => {
+                        for decl in decls {
+                            self.declaration(decl);
+                        }
+                        self.tail(last);
+                    }
+                    None => {
+                        self.emit(OpCode::Nil);
+                        self.emit(OpCode::Return);
+                    }
+                }
+                self.discard_scope();
+                self.current().depth = depth;
+            }
+            Stmt::Match {
+                keyword,
+                value,
+                arms,
+            } => {
+                self.line = keyword.line;
+                self.match_statement(value, arms, true);
+            }
+            Stmt::Return { .. } => self.statement(stmt),
+            _ => {
+                self.statement(stmt);
+                self.emit(OpCode::Nil);
+                self.emit(OpCode::Return);
+            }
+        }
+    }
+
+    fn match_statement(&mut self, value: &Expr, arms: &[MatchArm<Stmt>], tail: bool) {
+        self.expression(value);
+        self.begin_scope();
+        self.add_local(hidden());
+        let mut end_jumps = Vec::new();
+        for arm in arms {
+            let depth = self.current().depth;
+            let next = self.match_arm(&arm.pattern);
+            if tail {
+                self.tail_statement(&arm.body);
+                self.discard_scope();
+            } else {
+                self.statement(&arm.body);
+                self.end_scope();
+                end_jumps.push(self.emit_jump(OpCode::Jump));
+            }
+            self.current().depth = depth;
+            self.patch_jump(next);
+        }
+        self.emit(OpCode::NoMatch);
+        for jump in end_jumps {
+            self.patch_jump(jump);
+        }
+        self.end_scope();
+    }
+
+    /// Tests the value on top of the stack against `pattern` and declares
+    /// the bindings in a new scope. Variant patterns read their enum like a
+    /// variable first. Returns the jump to the next arm.
+    fn match_arm(&mut self, pattern: &Pattern) -> usize {
+        match pattern {
+            Pattern::Variant { enumeration, .. } => {
+                let enumeration = enumeration
+                    .get()
+                    .expect("bug: variant pattern wasn't resolved");
+                self.variable(enumeration, false);
+            }
+            _ => self.emit(OpCode::Nil),
+        }
+        let index = self.make_constant(Constant::Pattern(pattern.clone()));
+        self.emit_u16(OpCode::Match, index);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        let next = self.chunk().code.len() - 2;
+        self.begin_scope();
+        if let Pattern::Variant {
+            bindings: Some(bindings),
+            ..
+        } = pattern
+        {
+            for name in bindings.iter().filter(|n| **n != Identifier::WILDCARD) {
+                self.adjust_depth(1);
+                self.add_local(name.clone());
+            }
+        }
+        next
+    }
+
+    fn class_declaration(&mut self, decl: &ClassDecl) {
+        let class_slot = if self.current().scope == 0 {
+            None
+        } else {
+            // Declared before the methods so they can refer to the class
+            self.emit(OpCode::Nil);
+            Some(self.add_local(decl.name.clone()))
+        };
+
+        if let Some(superclass) = &decl.superclass {
+            self.begin_scope();
+            self.expression(superclass);
+            let slot = self.add_local(Identifier::SUPER);
+            self.emit_u8(OpCode::GetLocal, slot);
+        }
+        for trait_expr in &decl.traits {
+            self.expression(trait_expr);
+        }
+        let mut methods = Vec::new();
+        for method in &decl.methods {
+            let kind =
+                if method.kind == FunctionKind::Function && method.identifier == Identifier::INIT {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+            self.function(method, kind);
+            methods.push((method.identifier.clone(), method.kind));
+        }
+        let mut static_methods = Vec::new();
+        for method in &decl.static_methods {
+            self.function(method, FunctionType::Method);
+            static_methods.push(method.identifier.clone());
+        }
+
+        let n_values =
+            decl.superclass.iter().len() + decl.traits.len() + methods.len() + static_methods.len();
+        self.adjust_depth(1 - n_values as isize);
+        let proto = self.make_constant(Constant::Class(Rc::new(ClassProto {
+            name: decl.name.clone(),
+            has_superclass: decl.superclass.is_some(),
+            traits: u8::try_from(decl.traits.len()).expect("too many traits"),
+            methods,
+            static_methods,
+        })));
+        self.emit_u16(OpCode::Class, proto);
+
+        match class_slot {
+            None => {
+                let name = self.name_constant(&decl.name);
+                self.emit_u16(OpCode::DefineGlobal, name);
+            }
+            Some(slot) => {
+                self.emit_u8(OpCode::SetLocal, slot);
+                self.emit(OpCode::Pop);
+            }
+        }
+        if decl.superclass.is_some() {
+            self.end_scope();
+        }
+
+        // Static fields are initialized after the class is defined so they can refer to it
+        for (name, initializer) in &decl.static_fields {
+            self.variable(&decl.name, false);
+            self.expression(initializer);
+            let name = self.name_constant(name);
+            self.emit_u16(OpCode::SetProperty, name);
+            self.emit(OpCode::Pop);
+        }
+    }
+
+    /// Compiles a call, `tail` for the value of a `return` that can reuse
+    /// the frame
+    fn call(
+        &mut self,
+        callee: &Expr,
+        parens: &Token,
+        args: &[Expr],
+        named_args: &[(Identifier, Expr)],
+        tail: bool,
+    ) {
+        self.expression(callee);
+        for arg in args {
+            self.expression(arg);
+        }
+        for (_, arg) in named_args {
+            self.expression(arg);
+        }
+        self.line = parens.line;
+        let n_args = u8::try_from(args.len()).expect("too many arguments");
+        if named_args.is_empty() {
+            let op = if tail { OpCode::TailCall } else { OpCode::Call };
+            self.emit_u8(op, n_args);
+        } else {
+            let names = named_args.iter().map(|(name, _)| name.clone()).collect();
+            let names = self.make_constant(Constant::Names(names));
+            self.emit_u8(OpCode::CallNamed, n_args);
+            for byte in names.to_be_bytes() {
+                self.emit_byte(byte);
+            }
+        }
+        self.adjust_depth(-((args.len() + named_args.len()) as isize));
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        self.line = expr.line;
+        match &expr.kind {
+            ExprKind::Literal { value } => match value {
+                Object::Null => self.emit(OpCode::Nil),
+                Object::Boolean(true) => self.emit(OpCode::True),
+                Object::Boolean(false) => self.emit(OpCode::False),
+                Object::Number(n) => {
+                    let index = self.make_constant(Constant::Number(*n));
+                    self.emit_u16(OpCode::Constant, index);
+                }
+                Object::String(s) => {
+                    let index = self.make_constant(Constant::String(s.clone()));
+                    self.emit_u16(OpCode::Constant, index);
+                }
+                other => panic!("bug: unexpected literal '{other:?}'"),
+            },
+            ExprKind::Grouping { expr } => self.expression(expr),
+            ExprKind::Unary { op, right } => {
+                self.expression(right);
+                self.line = op.line;
+                match op.typ {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    _ => self.emit(OpCode::Not),
+                }
+            }
+            ExprKind::Binary { left, op, right } => {
+                self.expression(left);
+                self.expression(right);
+                self.line = op.line;
+                let op = match op.typ {
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    typ => panic!("bug: unexpected binary operator {typ:?}"),
+                };
+                self.emit(op);
+            }
+            ExprKind::Logical { left, op, right } => {
+                self.expression(left);
+                self.line = op.line;
+                let op = if op.typ == TokenType::Or {
+                    OpCode::JumpIfTruthy
+                } else {
+                    OpCode::JumpIfFalsy
+                };
+                let end = self.emit_jump(op);
+                self.emit(OpCode::Pop);
+                self.expression(right);
+                self.patch_jump(end);
+            }
+            ExprKind::Var { name } => self.variable(name, false),
+            ExprKind::Assign { name, expr } => {
+                self.expression(expr);
+                self.variable(name, true);
+            }
+            ExprKind::This { token } => self.variable(token, false),
+            ExprKind::Super { token: _, method } => {
+                self.variable(&Identifier::THIS, false);
+                self.variable(&Identifier::SUPER, false);
+                let method = self.name_constant(method);
+                self.emit_u16(OpCode::GetSuper, method);
+            }
+            ExprKind::Call {
+                callee,
+                parens,
+                args,
+                named_args,
+            } => self.call(callee, parens, args, named_args, false),
+            ExprKind::Get { name, object } => {
+                self.expression(object);
+                let name = self.name_constant(name);
+                self.emit_u16(OpCode::GetProperty, name);
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(value);
+                let name = self.name_constant(name);
+                self.emit_u16(OpCode::SetProperty, name);
+            }
+            ExprKind::Match {
+                keyword,
+                value,
+                arms,
+            } => {
+                self.line = keyword.line;
+                self.expression(value);
+                self.begin_scope();
+                let slot = self.add_local(hidden());
+                let mut end_jumps = Vec::new();
+                for arm in arms {
+                    let depth = self.current().depth;
+                    let next = self.match_arm(&arm.pattern);
+                    let n_bindings = self.current().depth - depth;
+                    self.expression(&arm.body);
+                    // Leaves the arm's value where the matched value was
+                    self.emit_u8(OpCode::Squash, n_bindings as u8 + 1);
+                    self.adjust_depth(-(n_bindings as isize + 1));
+                    self.discard_scope();
+                    end_jumps.push(self.emit_jump(OpCode::Jump));
+                    self.current().depth = depth;
+                    self.patch_jump(next);
+                }
+                self.emit(OpCode::NoMatch);
+                for jump in end_jumps {
+                    self.patch_jump(jump);
+                }
+                self.discard_scope();
+                self.current().depth = slot as usize + 1;
+            }
+            ExprKind::List { items } => {
+                if items
+                    .iter()
+                    .any(|item| matches!(item.kind, ExprKind::Spread { .. }))
+                {
+                    self.emit_u16(OpCode::List, 0);
+                    self.adjust_depth(1);
+                    for item in items {
+                        match &item.kind {
+                            ExprKind::Spread { expr } => {
+                                self.expression(expr);
+                                self.emit(OpCode::Extend);
+                            }
+                            _ => {
+                                self.expression(item);
+                                self.emit(OpCode::Append);
+                            }
+                        }
+                    }
+                } else {
+                    for item in items {
+                        self.expression(item);
+                    }
+                    let n_items = u16::try_from(items.len()).expect("too many items in list");
+                    self.emit_u16(OpCode::List, n_items);
+                    self.adjust_depth(1 - items.len() as isize);
+                }
+            }
+            ExprKind::Map { entries } => {
+                for (_, value) in entries {
+                    self.expression(value);
+                }
+                let keys = entries.iter().map(|(key, _)| key.clone()).collect();
+                let keys = self.make_constant(Constant::Keys(keys));
+                self.emit_u16(OpCode::Map, keys);
+                self.adjust_depth(1 - entries.len() as isize);
+            }
+            ExprKind::Spread { .. } => panic!("'{expr}' is only valid inside a list"),
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(OpCode::GetIndex);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.emit(OpCode::SetIndex);
+            }
+            ExprKind::Destructure { target, value } => {
+                self.expression(value);
+                self.assign_target(target);
+            }
+        }
+    }
+
+    /// Assigns the value on top of the stack to a target of a destructuring
+    /// assignment, leaving it there
+    fn assign_target(&mut self, target: &Expr) {
+        match &target.kind {
+            ExprKind::Var { name } => self.variable(name, true),
+            ExprKind::Get { object, name } => {
+                self.expression(object);
+                self.emit_u8(OpCode::Peek, 1);
+                let name = self.name_constant(name);
+                self.emit_u16(OpCode::SetProperty, name);
+                self.emit(OpCode::Pop);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit_u8(OpCode::Peek, 2);
+                self.emit(OpCode::SetIndex);
+                self.emit(OpCode::Pop);
+            }
+            ExprKind::List { items } => {
+                let rest = items.last().and_then(|item| match &item.kind {
+                    ExprKind::Spread { expr } => Some(expr),
+                    _ => None,
+                });
+                let n_items = items.len() - rest.iter().len();
+                let n_items = u8::try_from(n_items).expect("too many items to destructure");
+                self.emit_u8(OpCode::CheckList, n_items);
+                self.emit_byte(rest.is_some() as u8);
+                for (i, item) in items.iter().take(n_items as usize).enumerate() {
+                    self.emit(OpCode::Dup);
+                    let index = self.make_constant(Constant::Number(i as f64));
+                    self.emit_u16(OpCode::Constant, index);
+                    self.emit(OpCode::GetIndex);
+                    self.assign_target(item);
+                    self.emit(OpCode::Pop);
+                }
+                if let Some(rest) = rest {
+                    self.emit(OpCode::Dup);
+                    self.emit_u8(OpCode::RestList, n_items);
+                    self.assign_target(rest);
+                    self.emit(OpCode::Pop);
+                }
+            }
+            _ => panic!("bug: invalid assignment target '{target}'"),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Prints bytecode in a readable form, for `lox disasm` and `--trace`.
+
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+
+/// Disassembles `function` followed by the functions declared in it, each
+/// under a header with its name
+pub fn disassemble(function: &FunctionProto) -> String {
+    let mut out = String::new();
+    disassemble_function(function, &mut out);
+    out
+}
+
+fn disassemble_function(function: &FunctionProto, out: &mut String) {
+    writeln!(out, "== {} ==", function.name).unwrap();
+    let chunk = &function.chunk;
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, out);
+    }
+    for constant in &chunk.constants {
+        if let Constant::Function(function) = constant {
+            out.push('\n');
+            disassemble_function(function, out);
+        }
+    }
+}
+
+/// Writes the instruction at `offset` as a line with its offset, source line
+/// and operands, returns the offset of the next instruction
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    // The scanner counts lines from 0
+    let line = chunk.line(offset) + 1;
+    if offset > 0 && line == chunk.line(offset - 1) + 1 {
+        write!(out, "{offset:04}    | ").unwrap();
+    } else {
+        write!(out, "{offset:04} {line:4} ").unwrap();
+    }
+    let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+        writeln!(out, "<unknown opcode {}>", chunk.code[offset]).unwrap();
+        return offset + 1;
+    };
+
+    let byte = |i: usize| chunk.code[offset + i];
+    let short = |i: usize| chunk.read_u16(offset + i);
+    let constant = |i: usize| &chunk.constants[short(i) as usize];
+    let (operands, size) = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::SetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Closure
+        | OpCode::Class
+        | OpCode::Trait
+        | OpCode::Enum
+        | OpCode::Map
+        | OpCode::Field => (format!("{:4} {}", short(1), constant(1)), 3),
+        OpCode::Peek
+        | OpCode::Squash
+        | OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call
+        | OpCode::TailCall
+        | OpCode::RestList => (format!("{:4}", byte(1)), 2),
+        OpCode::List => (format!("{:4}", short(1)), 3),
+        OpCode::Jump
+        | OpCode::JumpIfFalse
+        | OpCode::JumpIfFalsy
+        | OpCode::JumpIfTruthy
+        | OpCode::IterNext => (
+            format!("{:4} -> {:04}", short(1), offset + 3 + short(1) as usize),
+            3,
+        ),
+        OpCode::Loop => (
+            format!("{:4} -> {:04}", short(1), offset + 3 - short(1) as usize),
+            3,
+        ),
+        OpCode::JumpIfArg => (
+            format!("{:4} -> {:04}", byte(1), offset + 4 + short(2) as usize),
+            4,
+        ),
+        OpCode::CallNamed => (format!("{:4} {}", byte(1), constant(2)), 4),
+        OpCode::CheckList => {
+            let rest = if byte(2) != 0 { " ..." } else { "" };
+            (format!("{:4}{rest}", byte(1)), 3)
+        }
+        OpCode::Match => (
+            format!(
+                "{:4} {} -> {:04}",
+                short(1),
+                constant(1),
+                offset + 5 + short(3) as usize
+            ),
+            5,
+        ),
+        OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Pop
+        | OpCode::Dup
+        | OpCode::CloseUpvalue
+        | OpCode::GetIndex
+        | OpCode::SetIndex
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Negate
+        | OpCode::Not
+        | OpCode::Print
+        | OpCode::Return
+        | OpCode::Append
+        | OpCode::Extend
+        | OpCode::Iter
+        | OpCode::IterEnd
+        | OpCode::Generator
+        | OpCode::Yield
+        | OpCode::NoMatch => (String::new(), 1),
+    };
+    let name = format!("{op:?}");
+    writeln!(out, "{}", format!("{name:<16} {operands}").trim_end()).unwrap();
+
+    // The upvalues a closure captures, as clox shows them
+    if op == OpCode::Closure {
+        if let Constant::Function(function) = constant(1) {
+            for upvalue in &function.upvalues {
+                let kind = if upvalue.is_local { "local" } else { "upvalue" };
+                writeln!(out, "{offset:04}    |   {kind} {}", upvalue.index).unwrap();
+            }
+        }
+    }
+    offset + size
+}
+
+#[test]
+fn test_disassemble() {
+    let mut chunk = Chunk::default();
+    let two = chunk.add_constant(Constant::Number(2.0));
+    chunk.write(OpCode::Constant as u8, 0);
+    chunk.write(two.to_be_bytes()[0], 0);
+    chunk.write(two.to_be_bytes()[1], 0);
+    chunk.write(OpCode::JumpIfFalse as u8, 1);
+    chunk.write(0, 1);
+    chunk.write(1, 1);
+    chunk.write(OpCode::Print as u8, 1);
+    chunk.write(OpCode::Return as u8, 2);
+    let function = FunctionProto {
+        name: crate::types::Identifier::SCRIPT,
+        parameters: Vec::new(),
+        rest: false,
+        upvalues: Vec::new(),
+        chunk,
+    };
+    assert_eq!(
+        disassemble(&function),
+        "== script ==\n\
+         0000    1 Constant            0 2.0\n\
+         0003    2 JumpIfFalse         1 -> 0007\n\
+         0006    | Print\n\
+         0007    3 Return\n"
+    );
+}
@@ -0,0 +1,155 @@
+use std::{fmt::Display, rc::Rc};
+
+use crate::{
+    class::ClassInstance,
+    interpreter::Interpreter,
+    types::{Arity, Callable, Identifier, Object},
+};
+
+#[derive(Debug)]
+pub struct Variant {
+    pub(crate) name: Identifier,
+    pub(crate) fields: Vec<Identifier>,
+}
+
+#[derive(Debug)]
+pub struct Enum {
+    pub(crate) name: Identifier,
+    pub(crate) variants: Vec<Variant>,
+}
+
+impl Enum {
+    pub fn new(name: Identifier, variants: Vec<Variant>) -> Self {
+        Self { name, variants }
+    }
+
+    /// Returns the value for a variant without payload, or the constructor of
+    /// a variant that carries one.
+    pub fn get(enumeration: &Rc<Enum>, name: &Identifier) -> Object {
+        let Some(variant) = enumeration.variants.iter().position(|v| &v.name == name) else {
+            panic!("enum {} has no variant '{name}'", enumeration.name);
+        };
+
+        if enumeration.variants[variant].fields.is_empty() {
+            Object::EnumValue(Rc::new(EnumValue {
+                enumeration: enumeration.clone(),
+                variant,
+                values: Vec::new(),
+            }))
+        } else {
+            Object::Callable(Rc::new(VariantConstructor {
+                enumeration: enumeration.clone(),
+                variant,
+            }))
+        }
+    }
+}
+
+/// An instance of one of the variants of an enum, along with its payload
+#[derive(Debug)]
+pub struct EnumValue {
+    pub(crate) enumeration: Rc<Enum>,
+    pub(crate) variant: usize,
+    pub(crate) values: Vec<Object>,
+}
+
+impl EnumValue {
+    pub fn variant(&self) -> &Variant {
+        &self.enumeration.variants[self.variant]
+    }
+
+    /// Whether both are the same variant of the same enum, payloads aside
+    pub fn same_variant(&self, other: &EnumValue) -> bool {
+        Rc::ptr_eq(&self.enumeration, &other.enumeration) && self.variant == other.variant
+    }
+
+    pub fn get(&self, name: &Identifier) -> Object {
+        let variant = self.variant();
+        let Some(i) = variant.fields.iter().position(|f| f == name) else {
+            panic!(
+                "variant {}.{} has no field '{name}'",
+                self.enumeration.name, variant.name
+            );
+        };
+        self.values[i].clone()
+    }
+}
+
+impl Display for EnumValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.enumeration.name, self.variant().name)?;
+        if !self.values.is_empty() {
+            write!(f, "(")?;
+            for (i, value) in self.values.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                Display::fmt(value, f)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+struct VariantConstructor {
+    enumeration: Rc<Enum>,
+    variant: usize,
+}
+
+impl Callable for VariantConstructor {
+    fn arity(&self) -> Arity {
+        Arity::exact(self.enumeration.variants[self.variant].fields.len())
+    }
+
+    fn call(&self, _: &mut Interpreter, args: &[Object]) -> Object {
+        Object::EnumValue(Rc::new(EnumValue {
+            enumeration: self.enumeration.clone(),
+            variant: self.variant,
+            values: args.to_vec(),
+        }))
+    }
+
+    fn bind(&self, _instance: &Rc<ClassInstance>) -> Object {
+        unimplemented!("can't bind a variant constructor")
+    }
+}
+
+#[test]
+fn test_payload_equality() {
+    use crate::{builtins::get_builtins, class::Class};
+
+    let mut interpreter = Interpreter::default();
+    let interner = interpreter.interner().clone();
+    let enumeration = Rc::new(Enum::new(
+        interner.intern("E"),
+        vec![Variant {
+            name: interner.intern("A"),
+            fields: vec![interner.intern("x")],
+        }],
+    ));
+    let wrap = |value: Object| {
+        Object::EnumValue(Rc::new(EnumValue {
+            enumeration: enumeration.clone(),
+            variant: 0,
+            values: vec![value],
+        }))
+    };
+    let builtins = get_builtins(&interner);
+    let (clock, random) = (builtins[0].1.clone(), builtins[1].1.clone());
+    assert!(interpreter.is_equal(&wrap(clock.clone()), &wrap(clock.clone())));
+    assert!(!interpreter.is_equal(&wrap(clock), &wrap(random)));
+
+    let class = Rc::new(Class::new(
+        interner.intern("Foo"),
+        None,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    ));
+    let a = Class::instantiate(&class, &mut interpreter, &[], &[]);
+    let b = Class::instantiate(&class, &mut interpreter, &[], &[]);
+    assert!(interpreter.is_equal(&wrap(a.clone()), &wrap(a.clone())));
+    assert!(!interpreter.is_equal(&wrap(a), &wrap(b)));
+}
@@ -1,23 +1,21 @@
-use std::{
-    cell::RefCell,
-    collections::{hash_map::Entry, HashMap},
-    fmt::Debug,
-    rc::Rc,
-};
+use std::{cell::RefCell, fmt::Debug, rc::Rc};
 
-use crate::types::{Identifier, Object};
+use crate::types::Object;
 
 pub type EnvRef = Rc<RefCell<Environment>>;
-pub type Values = Rc<RefCell<HashMap<Identifier, Object>>>;
 
+/// The local variables of a scope. The resolver gives each variable a slot,
+/// its position in the scope, and variables are defined in the same order
+/// so defining one just appends its value.
+#[derive(Default)]
 pub struct Environment {
     pub parent: Option<EnvRef>,
-    values: Values,
+    values: Vec<Object>,
 }
 
 impl Debug for Environment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(&self.values.borrow(), f)?;
+        Debug::fmt(&self.values, f)?;
         write!(f, "->")?;
         Debug::fmt(&self.parent, f)
     }
@@ -27,65 +25,42 @@ impl Environment {
     pub fn new_ref(parent: Option<EnvRef>) -> EnvRef {
         Rc::new(RefCell::new(Self {
             parent,
-            values: Default::default(),
+            values: Vec::new(),
         }))
     }
 
-    pub fn define(&self, name: Identifier, value: Object) {
-        self.values.borrow_mut().insert(name, value);
+    /// Whether this is the outermost environment, where globals live
+    pub fn is_global(&self) -> bool {
+        self.parent.is_none()
     }
 
-    pub fn get(&self, name: &Identifier) -> Option<Object> {
-        let value = self.values.borrow().get(name).cloned();
-        if value.is_none() {
-            return self.parent.as_ref().and_then(|p| p.borrow().get(name));
-        }
-
-        value
-    }
-
-    pub fn assign_at(&self, distance: usize, name: Identifier, value: Object) {
-        let ancestor: Values = self.ancestor(distance);
-        ancestor.borrow_mut().insert(name, value);
+    pub fn define(&mut self, value: Object) {
+        self.values.push(value);
     }
 
-    pub fn get_at(&self, distance: usize, name: &Identifier) -> Object {
-        let ancestor: Values = self.ancestor(distance);
-        let x = ancestor.borrow().get(name).cloned().unwrap_or_else(|| {
-            panic!("could not find {name:?} in scope {ancestor:?} for distance {distance}")
-        });
-        x
-    }
-
-    fn ancestor(&self, distance: usize) -> Values {
-        if distance == 0 {
-            return self.values.clone();
+    pub fn get_at(&self, depth: usize, slot: usize) -> Object {
+        if depth > 0 {
+            return self.parent().borrow().get_at(depth - 1, slot);
         }
-        self.parent
-            .as_ref()
-            .map(|parent| parent.borrow().ancestor(distance - 1).clone())
-            .unwrap()
+        self.values
+            .get(slot)
+            .cloned()
+            .unwrap_or_else(|| panic!("bug: slot {slot} isn't defined in {self:?}"))
     }
 
-    pub fn mutate(&self, name: &Identifier, value: Object) -> Option<Object> {
-        let name = name.clone();
-        match self.values.borrow_mut().entry(name.clone()) {
-            Entry::Occupied(mut entry) => Some(entry.insert(value)),
-            Entry::Vacant(_) => {
-                return self
-                    .parent
-                    .as_ref()
-                    .and_then(|p| p.borrow_mut().mutate(&name, value))
-            }
+    pub fn assign_at(&mut self, depth: usize, slot: usize, value: Object) {
+        if depth > 0 {
+            return self.parent().borrow_mut().assign_at(depth - 1, slot, value);
+        }
+        match self.values.get_mut(slot) {
+            Some(old) => *old = value,
+            None => panic!("bug: slot {slot} isn't defined in {self:?}"),
         }
     }
-}
 
-impl Default for Environment {
-    fn default() -> Self {
-        Self {
-            parent: None,
-            values: Default::default(),
-        }
+    fn parent(&self) -> &EnvRef {
+        self.parent
+            .as_ref()
+            .expect("bug: variable resolved past the outermost scope")
     }
 }
@@ -0,0 +1,475 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::ClassInstance,
+    environment::{EnvRef, Environment},
+    interpreter::Interpreter,
+    iter::Iter,
+    syntax::{Declaration, FunctionStmt, Stmt},
+    types::{Arity, Callable, Identifier, Object},
+};
+
+/// A suspended call to a generator function. Each backend suspends the
+/// call its own way, the generator keeps the value `hasNext()` peeked at.
+pub struct Generator {
+    name: Identifier,
+    state: RefCell<GeneratorState>,
+}
+
+struct GeneratorState {
+    coroutine: Box<dyn Coroutine>,
+    /// A value produced by `hasNext()` that `next()` hasn't returned yet
+    peeked: Option<Option<Object>>,
+}
+
+/// The suspended body of a generator
+pub trait Coroutine {
+    /// Runs until the next `yield`, `None` once the body is done
+    fn resume(&mut self, interpreter: &mut Interpreter) -> Option<Object>;
+}
+
+/// A generator body run by the tree-walking interpreter.
+///
+/// The interpreter evaluates code recursively on the Rust stack, so it can't
+/// suspend in the middle of an arbitrary expression. Instead `yield` is a
+/// statement and the generator keeps its own stack of the statements that
+/// are in progress, each frame pointing into the function body by position.
+/// Statements that don't contain a `yield` are run by the interpreter as usual.
+pub struct TreeCoroutine {
+    decl: Rc<FunctionStmt>,
+    frames: Vec<Frame>,
+}
+
+/// A statement in progress. `env` is the environment its children run in
+enum Frame {
+    /// The next declaration of the block to run
+    Block {
+        next: usize,
+        env: EnvRef,
+    },
+    /// The branch that was taken, `entered` once it started running
+    If {
+        then_branch: bool,
+        entered: bool,
+        env: EnvRef,
+    },
+    While {
+        env: EnvRef,
+    },
+    ForIn {
+        iter: Iter,
+        env: EnvRef,
+    },
+    /// The arm that matched, `entered` once it started running
+    Match {
+        arm: usize,
+        entered: bool,
+        env: EnvRef,
+    },
+}
+
+impl Frame {
+    fn env(&self) -> &EnvRef {
+        match self {
+            Frame::Block { env, .. }
+            | Frame::If { env, .. }
+            | Frame::While { env }
+            | Frame::ForIn { env, .. }
+            | Frame::Match { env, .. } => env,
+        }
+    }
+}
+
+/// The statement a frame belongs to, the root frame is the function body
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Body(&'a Vec<Declaration>),
+    Stmt(&'a Stmt),
+}
+
+impl<'a> Node<'a> {
+    /// The statement currently running under `frame`
+    fn child(self, frame: &Frame) -> &'a Stmt {
+        match (self, frame) {
+            (Node::Body(decls), Frame::Block { next, .. })
+            | (Node::Stmt(Stmt::Block(decls)), Frame::Block { next, .. }) => {
+                match &decls[next - 1] {
+                    Declaration::Statement(stmt) => stmt,
+                    Declaration::Var { .. } => panic!("bug: var declarations can't yield"),
+                }
+            }
+            (
+                Node::Stmt(Stmt::If {
+                    then_branch,
+                    else_branch,
+                    ..
+                }),
+                Frame::If {
+                    then_branch: taken, ..
+                },
+            ) => {
+                if *taken {
+                    then_branch
+                } else {
+                    else_branch.as_ref().expect("bug: no else branch")
+                }
+            }
+            (Node::Stmt(Stmt::While { body, .. }), Frame::While { .. })
+            | (Node::Stmt(Stmt::ForIn { body, .. }), Frame::ForIn { .. }) => body,
+            (Node::Stmt(Stmt::Match { arms, .. }), Frame::Match { arm, .. }) => &arms[*arm].body,
+            _ => panic!("bug: generator frame doesn't match its statement"),
+        }
+    }
+}
+
+impl Generator {
+    pub fn new(name: Identifier, coroutine: Box<dyn Coroutine>) -> Self {
+        Self {
+            name,
+            state: RefCell::new(GeneratorState {
+                coroutine,
+                peeked: None,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &Identifier {
+        &self.name
+    }
+
+    pub fn has_next(&self, interpreter: &mut Interpreter) -> bool {
+        let mut state = self.state();
+        if state.peeked.is_none() {
+            let value = state.coroutine.resume(interpreter);
+            state.peeked = Some(value);
+        }
+        matches!(state.peeked, Some(Some(_)))
+    }
+
+    /// Resumes the generator until its next `yield`, `None` once it's done
+    pub fn resume(&self, interpreter: &mut Interpreter) -> Option<Object> {
+        let mut state = self.state();
+        match state.peeked.take() {
+            Some(value) => value,
+            None => state.coroutine.resume(interpreter),
+        }
+    }
+
+    fn state(&self) -> std::cell::RefMut<'_, GeneratorState> {
+        self.state.try_borrow_mut().unwrap_or_else(|_| {
+            panic!(
+                "generator {} can't be resumed while it's running",
+                self.name
+            )
+        })
+    }
+}
+
+impl Coroutine for TreeCoroutine {
+    fn resume(&mut self, interpreter: &mut Interpreter) -> Option<Object> {
+        let caller_env = interpreter.environment();
+        let value = self.step(interpreter);
+        interpreter.set_environment(caller_env);
+        if value.is_none() {
+            self.frames.clear();
+        }
+        value
+    }
+}
+
+impl TreeCoroutine {
+    /// Creates a coroutine that will run `decl`'s body in `env`, which already
+    /// holds the arguments of the call
+    pub fn new(decl: Rc<FunctionStmt>, env: EnvRef) -> Self {
+        Self {
+            decl,
+            frames: vec![Frame::Block { next: 0, env }],
+        }
+    }
+
+    fn step(&mut self, interpreter: &mut Interpreter) -> Option<Object> {
+        let frames = &mut self.frames;
+        loop {
+            let top = frames.len().checked_sub(1)?;
+
+            let mut node = Node::Body(&self.decl.body);
+            for frame in &frames[..top] {
+                node = Node::Stmt(node.child(frame));
+            }
+            interpreter.set_environment(frames[top].env().clone());
+
+            let child = match (&mut frames[top], node) {
+                (Frame::Block { next, .. }, Node::Body(decls))
+                | (Frame::Block { next, .. }, Node::Stmt(Stmt::Block(decls))) => {
+                    let Some(decl) = decls.get(*next) else {
+                        frames.pop();
+                        continue;
+                    };
+                    *next += 1;
+                    match decl {
+                        Declaration::Statement(stmt) if contains_yield(stmt) => stmt,
+                        decl => {
+                            // A return anywhere finishes the generator
+                            interpreter.execute(decl).ok()?;
+                            continue;
+                        }
+                    }
+                }
+                // Branches run once, they're done when control comes back to them
+                (Frame::If { entered, .. }, _) | (Frame::Match { entered, .. }, _) => {
+                    if *entered {
+                        frames.pop();
+                        continue;
+                    }
+                    *entered = true;
+                    node.child(&frames[top])
+                }
+                (Frame::While { .. }, Node::Stmt(Stmt::While { condition, body })) => {
+                    match interpreter.eval(condition) {
+                        Object::Boolean(true) => body,
+                        Object::Boolean(false) => {
+                            frames.pop();
+                            continue;
+                        }
+                        literal => panic!("while condition can only be boolean, got '{literal:?}'"),
+                    }
+                }
+                (Frame::ForIn { iter, env }, Node::Stmt(Stmt::ForIn { binding, body, .. })) => {
+                    let Some(item) = iter.next(interpreter) else {
+                        frames.pop();
+                        continue;
+                    };
+                    // Every iteration gets its own variables, stored in the frame's environment
+                    let parent = env.borrow().parent.clone();
+                    *env = Environment::new_ref(parent);
+                    interpreter.set_environment(env.clone());
+                    interpreter.bind(binding, item);
+                    body
+                }
+                _ => panic!("bug: generator frame doesn't match its statement"),
+            };
+
+            // Children that can't yield run to completion right away
+            if !contains_yield(child) {
+                interpreter.execute_stmt(child).ok()?;
+                continue;
+            }
+
+            let env = interpreter.environment();
+            let frame = match child {
+                Stmt::Yield { value, .. } => return Some(interpreter.eval(value)),
+                Stmt::Block(_) => Frame::Block {
+                    next: 0,
+                    env: Environment::new_ref(Some(env)),
+                },
+                Stmt::If {
+                    condition,
+                    else_branch,
+                    ..
+                } => {
+                    let taken = match interpreter.eval(condition) {
+                        Object::Boolean(value) => value,
+                        literal => panic!("if condition can only be boolean, got '{literal:?}'"),
+                    };
+                    if !taken && else_branch.is_none() {
+                        continue;
+                    }
+                    Frame::If {
+                        then_branch: taken,
+                        entered: false,
+                        env,
+                    }
+                }
+                Stmt::While { .. } => Frame::While { env },
+                Stmt::ForIn { iterable, .. } => {
+                    let iterable = interpreter.eval(iterable);
+                    let iter = Iter::new(interpreter, iterable);
+                    Frame::ForIn {
+                        iter,
+                        env: Environment::new_ref(Some(env)),
+                    }
+                }
+                Stmt::Match { value, arms, .. } => {
+                    let value = interpreter.eval(value);
+                    let matched = arms.iter().enumerate().find_map(|(i, arm)| {
+                        interpreter
+                            .match_pattern(&arm.pattern, &value)
+                            .map(|env| (i, env))
+                    });
+                    let Some((arm, env)) = matched else {
+                        panic!("no match arm matched value {value}");
+                    };
+                    Frame::Match {
+                        arm,
+                        entered: false,
+                        env,
+                    }
+                }
+                _ => panic!("bug: statement can't contain a yield"),
+            };
+            frames.push(frame);
+        }
+    }
+}
+
+/// Whether a function body yields, which makes the function a generator
+pub fn body_yields(body: &[Declaration]) -> bool {
+    body.iter().any(|decl| match decl {
+        Declaration::Statement(stmt) => contains_yield(stmt),
+        Declaration::Var { .. } => false,
+    })
+}
+
+/// Whether `stmt` yields, not counting the functions declared inside it
+pub fn contains_yield(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Yield { .. } => true,
+        Stmt::Block(decls) => body_yields(decls),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => contains_yield(then_branch) || else_branch.as_deref().is_some_and(contains_yield),
+        Stmt::While { body, .. } | Stmt::ForIn { body, .. } => contains_yield(body),
+        Stmt::Match { arms, .. } => arms.iter().any(|arm| contains_yield(&arm.body)),
+        Stmt::Expr(_)
+        | Stmt::FunctionDecl(_)
+        | Stmt::ClassDecl(_)
+        | Stmt::TraitDecl(_)
+        | Stmt::EnumDecl(_)
+        | Stmt::Print(_)
+        | Stmt::Return { .. } => false,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum GeneratorMethodKind {
+    HasNext,
+    Next,
+}
+
+/// `hasNext()` and `next()`, so generators follow the iterator protocol
+struct GeneratorMethod {
+    generator: Rc<Generator>,
+    kind: GeneratorMethodKind,
+}
+
+pub fn get_method(generator: &Rc<Generator>, name: &Identifier) -> Object {
+    let kind = if *name == Identifier::HAS_NEXT {
+        GeneratorMethodKind::HasNext
+    } else if *name == Identifier::NEXT {
+        GeneratorMethodKind::Next
+    } else {
+        panic!("generators have no property '{name}'")
+    };
+    Object::Callable(Rc::new(GeneratorMethod {
+        generator: generator.clone(),
+        kind,
+    }))
+}
+
+impl Callable for GeneratorMethod {
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, _: &[Object]) -> Object {
+        match self.kind {
+            GeneratorMethodKind::HasNext => Object::Boolean(self.generator.has_next(interpreter)),
+            GeneratorMethodKind::Next => self
+                .generator
+                .resume(interpreter)
+                .unwrap_or_else(|| panic!("generator {} is exhausted", self.generator.name())),
+        }
+    }
+
+    fn bind(&self, _instance: &Rc<ClassInstance>) -> Object {
+        unimplemented!("can't bind a generator method")
+    }
+}
+
+/// Runs `source` on the tree backend and returns the global `out`
+#[cfg(test)]
+fn run_source(source: &str) -> String {
+    let (ast, mut interpreter) = crate::resolver::resolve_source(source);
+    interpreter.interpret(ast);
+    interpreter
+        .get_global(&interpreter.interner().intern("out"))
+        .to_string()
+}
+
+#[cfg(test)]
+const COLLECT: &str = "
+    fun collect(items) {
+      var out = \"\";
+      for (var item in items) out = out + str(item) + \" \";
+      return out;
+    }
+";
+
+#[test]
+fn test_yield_in_blocks() {
+    let source = "
+        fun loop(n) {
+          var i = 0;
+          while (i < n) {
+            yield i;
+            i = i + 1;
+          }
+        }
+        fun branch(x) {
+          if (x) { yield \"then\"; } else { yield \"else\"; }
+          yield \"after\";
+        }
+        fun doubled(items) {
+          for (var item in items) yield item * 2;
+        }
+        enum Shape { Dot, Line(a, b) }
+        fun points(shape) {
+          match (shape) {
+            Dot => yield 0;
+            Line(a, b) => {
+              yield a;
+              yield b;
+            }
+          }
+        }
+        var out = collect(loop(3)) + collect(branch(true)) + collect(branch(false))
+          + collect(doubled([1, 2])) + collect(points(Shape.Dot))
+          + collect(points(Shape.Line(1, 2)));
+    ";
+    assert_eq!(
+        run_source(&format!("{COLLECT}{source}")),
+        "0 1 2 then after else after 2 4 0 1 2 "
+    );
+}
+
+#[test]
+fn test_return_ends_generator() {
+    let source = "
+        fun early(n) {
+          yield 1;
+          while (true) {
+            if (n == 0) return;
+            yield n;
+            n = n - 1;
+          }
+          yield \"unreachable\";
+        }
+        var out = collect(early(2));
+    ";
+    assert_eq!(run_source(&format!("{COLLECT}{source}")), "1 2 1 ");
+}
+
+#[test]
+#[should_panic(expected = "generator once is exhausted")]
+fn test_next_after_exhaustion() {
+    run_source(
+        "
+        fun once() { yield 1; }
+        var gen = once();
+        gen.next();
+        gen.next();
+        ",
+    );
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What the program running the interpreter lets scripts see and do.
+/// Embedders running untrusted scripts can take capabilities away.
+#[derive(Debug, Clone)]
+pub struct Host {
+    /// Whether scripts can read and write files
+    pub filesystem: bool,
+    /// Returned by `args()`, the command line arguments after the script name
+    pub args: Vec<String>,
+    /// Variables visible to `getenv()`, `None` uses the process environment
+    pub env: Option<HashMap<String, String>>,
+    /// Where `clock()` reads the time from
+    pub clock: Clock,
+    /// Source of `random()` and `randomInt()`
+    pub rng: Rng,
+}
+
+impl Host {
+    pub fn getenv(&self, name: &str) -> Option<String> {
+        match &self.env {
+            Some(env) => env.get(name).cloned(),
+            None => std::env::var(name).ok(),
+        }
+    }
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self {
+            filesystem: true,
+            args: Vec::new(),
+            env: None,
+            clock: Clock::System,
+            rng: Rng::from_time(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clock {
+    System,
+    /// Always returns the same time, for reproducible output
+    Fixed(f64),
+}
+
+impl Clock {
+    /// Seconds since the Unix epoch
+    pub fn now(&self) -> f64 {
+        match self {
+            Clock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64(),
+            Clock::Fixed(time) => *time,
+        }
+    }
+}
+
+/// A small pseudo random number generator (SplitMix64). The same seed always
+/// produces the same numbers, on every platform.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn from_time() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Self::seeded(now.as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, every one equally likely
+    pub fn below(&mut self, n: u64) -> u64 {
+        // Draws in the first `2^64 % n` values would make the low results
+        // more likely than the others, so they're drawn again
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let x = self.next_u64();
+            if x >= threshold {
+                return x % n;
+            }
+        }
+    }
+
+    /// A number in `0..1`
+    pub fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa exactly
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[test]
+fn test_below() {
+    let mut rng = Rng::seeded(7);
+    assert!((0..100).all(|_| rng.below(1) == 0));
+    assert!((0..100).all(|_| rng.below(6) < 6));
+    assert!((0..100).all(|_| rng.below(u64::MAX) < u64::MAX));
+}
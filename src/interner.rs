@@ -0,0 +1,75 @@
+//! Interned strings. Identifiers are hashed and compared on every variable,
+//! field and method lookup, so each one carries a symbol, its index in the
+//! interpreter's table of names. String literals are interned too, so equal
+//! literals share one allocation and compare by pointer first.
+//!
+//! The parser, the compiler and the `.loxc` loader intern into the table of
+//! the interpreter that runs what they produce. Identifiers from different
+//! tables can't be compared.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::types::{Identifier, PREINTERNED};
+
+#[derive(Debug, Default)]
+struct Table {
+    names: Vec<Rc<str>>,
+    symbols: HashMap<Rc<str>, u32>,
+}
+
+impl Table {
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = u32::try_from(self.names.len()).expect("too many distinct names");
+        let name: Rc<str> = name.into();
+        self.names.push(name.clone());
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+}
+
+/// A table of names, clones share it. The names the interpreter looks up
+/// itself are interned first, so their symbols are known up front.
+#[derive(Debug, Clone)]
+pub struct Interner(Rc<RefCell<Table>>);
+
+impl Default for Interner {
+    fn default() -> Self {
+        let mut table = Table::default();
+        for name in PREINTERNED {
+            table.intern(name);
+        }
+        Self(Rc::new(RefCell::new(table)))
+    }
+}
+
+impl Interner {
+    /// The identifier for `name`, with the same symbol every time
+    pub fn intern(&self, name: &str) -> Identifier {
+        let mut table = self.0.borrow_mut();
+        let symbol = table.intern(name);
+        Identifier::new(symbol, table.names[symbol as usize].clone())
+    }
+
+    /// The shared copy of `s`
+    pub fn intern_str(&self, s: &str) -> Rc<str> {
+        let mut table = self.0.borrow_mut();
+        let symbol = table.intern(s);
+        table.names[symbol as usize].clone()
+    }
+}
+
+#[test]
+fn test_intern() {
+    let interner = Interner::default();
+    let a = interner.intern("some_name");
+    assert_eq!(interner.intern("some_name"), a);
+    assert_ne!(interner.intern("other_name"), a);
+    assert_eq!(a.name(), "some_name");
+    assert!(Rc::ptr_eq(
+        &interner.intern_str("some_name"),
+        &interner.intern_str("some_name")
+    ));
+}
@@ -1,51 +1,114 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
     builtins::get_builtins,
-    callable::Function,
-    class::Class,
+    callable::{Function, FunctionRef, TailCall},
+    class::{Class, Mixins, Trait},
+    enums::{Enum, Variant},
     environment::{EnvRef, Environment},
+    generator,
+    host::Host,
+    interner::Interner,
+    iter::Iter,
+    process,
     scanner::{Token, TokenType},
-    syntax::{Declaration, Expr, ExprKind, Program, Stmt},
+    strings,
+    syntax::{Binding, Declaration, Expr, ExprKind, FunctionKind, Pattern, Program, Stmt},
     types::{Identifier, Object},
+    vm::Vm,
 };
 
 type Flow<T> = Result<T, T>;
 
+/// Where the resolver found a local variable: how many scopes up from the
+/// current one, and its slot in that scope
+#[derive(Debug, Clone, Copy)]
+pub struct Local {
+    pub depth: usize,
+    pub slot: usize,
+}
+
 #[derive(Debug)]
 pub struct Interpreter {
     environment: EnvRef,
-    globals: EnvRef,
-    locals: HashMap<u64, usize>,
+    /// Variables the resolver didn't find in a local scope, looked up by name
+    globals: HashMap<Identifier, Object>,
+    locals: HashMap<u64, Local>,
+    /// Calls the resolver found in tail position, `return f(x);`
+    tail_calls: HashSet<u64>,
+    /// Set by a tail call for the function returning to make it
+    pub(crate) tail_call: Option<TailCall>,
+    host: Host,
+    /// The names of the program this interpreter runs
+    interner: Interner,
+    /// State of the `vm` backend
+    pub(crate) vm: Vm,
 }
 
-impl Interpreter {
-    pub fn new() -> Self {
-        // Initialize globals
-        let globals = EnvRef::default();
-        for (name, builtin) in get_builtins() {
-            globals
-                .borrow_mut()
-                .define(Identifier(name.into()), builtin);
-        }
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::with_host(Host::default())
+    }
+}
 
-        let environment = globals.clone();
+impl Interpreter {
+    pub fn with_host(host: Host) -> Self {
+        let interner = Interner::default();
+        let globals = get_builtins(&interner)
+            .into_iter()
+            .map(|(name, builtin)| (interner.intern(name), builtin))
+            .collect();
 
         Self {
             globals,
-            environment,
+            environment: Environment::new_ref(None),
             locals: HashMap::default(),
+            tail_calls: HashSet::default(),
+            tail_call: None,
+            host,
+            interner,
+            vm: Vm::default(),
         }
     }
 
-    pub fn interpret(&mut self, prog: Program) {
-        match prog {
-            Program::Declarations(decls) => {
-                for decl in decls {
-                    let _ = self.execute(&decl);
-                }
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    pub fn host_mut(&mut self) -> &mut Host {
+        &mut self.host
+    }
+
+    /// The table the parser, compiler and loader intern this program's names in
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    /// Runs the program, returning the status it passed to `exit()` or 0
+    pub fn interpret(&mut self, prog: Program) -> i32 {
+        let Program::Declarations(decls) = prog;
+        self.catch_exit(|interpreter| {
+            for decl in decls {
+                let _ = interpreter.execute(&decl);
             }
-        }
+        })
+    }
+
+    /// Runs `run`, returning the status it passed to `exit()` or 0. Unwinding
+    /// out of `exit()` skips the returns of the calls in progress, so the
+    /// state they would have restored is put back here.
+    pub(crate) fn catch_exit(&mut self, run: impl FnOnce(&mut Self)) -> i32 {
+        let environment = self.environment.clone();
+        let depth = self.vm.depth();
+        let status = process::catch_exit(|| run(self));
+        self.environment = environment;
+        self.tail_call = None;
+        self.vm.unwind(depth);
+        status
     }
 
     pub fn execute_stmt(&mut self, stmt: &Stmt) -> Flow<Object> {
@@ -70,7 +133,7 @@ impl Interpreter {
             }
             Stmt::Print(expr) => {
                 let value = self.eval(expr);
-                println!("{value}");
+                println!("{}", self.stringify(&value));
                 Flow::Ok(Object::Null)
             }
             Stmt::While { condition, body } => loop {
@@ -84,6 +147,23 @@ impl Interpreter {
                     return Flow::Ok(Object::Null);
                 };
             },
+            Stmt::ForIn {
+                binding,
+                iterable,
+                body,
+            } => {
+                let iterable = self.eval(iterable);
+                let mut iter = Iter::new(self, iterable);
+                while let Some(item) = iter.next(self) {
+                    let env = Environment::new_ref(Some(self.environment.clone()));
+                    let prev_env = std::mem::replace(&mut self.environment, env);
+                    self.bind(binding, item);
+                    let result = self.execute_stmt(body);
+                    self.environment = prev_env;
+                    result?;
+                }
+                Flow::Ok(Object::Null)
+            }
             Stmt::Block(decls) => Flow::Ok(
                 self.execute_block(decls, Environment::new_ref(Some(self.environment.clone())))?,
             ),
@@ -93,12 +173,24 @@ impl Interpreter {
                     self.environment.clone(),
                     false,
                 )));
-                self.environment
-                    .borrow_mut()
-                    .define(function_stmt.identifier.clone(), fun);
+                self.define(function_stmt.identifier.clone(), fun);
                 Flow::Ok(Object::Null)
             }
-            Stmt::Return { value } => Flow::Err(self.eval(value)),
+            Stmt::Return { value } => match &value.kind {
+                ExprKind::Call {
+                    callee,
+                    parens: _,
+                    args,
+                    named_args,
+                } if self.tail_calls.contains(&value.id) => {
+                    Flow::Err(self.eval_tail_call(callee, args, named_args))
+                }
+                _ => Flow::Err(self.eval(value)),
+            },
+            // Generators drive their own bodies, see `Generator::resume`
+            Stmt::Yield { keyword, .. } => {
+                panic!("[line {}] 'yield' outside of a generator", keyword.line + 1)
+            }
             Stmt::ClassDecl(class_decl) => {
                 let superclass = class_decl.superclass.as_ref().map(|superclass| {
                     let superclass = self.eval(superclass);
@@ -108,36 +200,126 @@ impl Interpreter {
                     superclass
                 });
 
-                self.environment
-                    .borrow_mut()
-                    .define(class_decl.name.clone(), Object::Null);
-
                 if let Some(superclass) = &superclass {
                     self.environment = Environment::new_ref(Some(self.environment.clone()));
                     self.environment
                         .borrow_mut()
-                        .define("super".into(), Object::Class(superclass.clone()));
+                        .define(Object::Class(superclass.clone()));
                 }
 
-                let mut methods = HashMap::new();
+                // Trait methods are copied into the class, its own methods take precedence
+                let mut mixins = Mixins::default();
+                for trait_expr in &class_decl.traits {
+                    let Object::Trait(mixin) = self.eval(trait_expr) else {
+                        panic!("'{trait_expr}' is not a trait!");
+                    };
+                    mixins.add(&class_decl.name, &mixin, |name, kind| {
+                        (class_decl.methods.iter())
+                            .any(|method| &method.identifier == name && method.kind == kind)
+                    });
+                }
+                let Mixins {
+                    mut methods,
+                    mut getters,
+                    mut setters,
+                    ..
+                } = mixins;
+
                 for method in &class_decl.methods {
-                    let function = Rc::new(Function::new(
+                    let is_initializer = method.kind == FunctionKind::Function
+                        && method.identifier == Identifier::INIT;
+                    let function: FunctionRef = Rc::new(Function::new(
+                        method.clone(),
+                        self.environment.clone(),
+                        is_initializer,
+                    ));
+                    let table = match method.kind {
+                        FunctionKind::Function => &mut methods,
+                        FunctionKind::Getter => &mut getters,
+                        FunctionKind::Setter => &mut setters,
+                    };
+                    table.insert(method.identifier.clone(), function);
+                }
+
+                let mut static_methods = HashMap::new();
+                for method in &class_decl.static_methods {
+                    let function: FunctionRef = Rc::new(Function::new(
                         method.clone(),
                         self.environment.clone(),
-                        method.identifier.as_ref() == "init",
+                        false,
                     ));
-                    methods.insert(method.identifier.clone(), function);
+                    static_methods.insert(method.identifier.clone(), function);
                 }
-                let class = Class::new(class_decl.name.clone(), superclass.clone(), methods);
+
+                let class = Rc::new(Class::new(
+                    class_decl.name.clone(),
+                    superclass.clone(),
+                    methods,
+                    getters,
+                    setters,
+                    static_methods,
+                ));
 
                 if superclass.is_some() {
                     let parent = self.environment.borrow().parent.clone().unwrap();
                     self.environment = parent;
                 }
 
-                self.environment
-                    .borrow_mut()
-                    .mutate(&class_decl.name, Object::Class(class.into()));
+                self.define(class_decl.name.clone(), Object::Class(class.clone()));
+
+                // Static fields are initialized after the class is defined so they can refer to it
+                for (name, initializer) in &class_decl.static_fields {
+                    let value = self.eval(initializer);
+                    class.set(name.clone(), value);
+                }
+                Flow::Ok(Object::Null)
+            }
+            Stmt::EnumDecl(enum_decl) => {
+                let variants = enum_decl
+                    .variants
+                    .iter()
+                    .map(|(name, fields)| Variant {
+                        name: name.clone(),
+                        fields: fields.clone(),
+                    })
+                    .collect();
+                let enumeration = Enum::new(enum_decl.name.clone(), variants);
+                self.define(enum_decl.name.clone(), Object::Enum(Rc::new(enumeration)));
+                Flow::Ok(Object::Null)
+            }
+            Stmt::Match {
+                keyword: _,
+                value,
+                arms,
+            } => {
+                let value = self.eval(value);
+                for arm in arms {
+                    if let Some(env) = self.match_pattern(&arm.pattern, &value) {
+                        let prev_env = std::mem::replace(&mut self.environment, env);
+                        let result = self.execute_stmt(&arm.body);
+                        self.environment = prev_env;
+                        return result;
+                    }
+                }
+                panic!("no match arm matched value {value}");
+            }
+            Stmt::TraitDecl(trait_decl) => {
+                let mut mixin = Trait::new(trait_decl.name.clone());
+                for method in &trait_decl.methods {
+                    let function: FunctionRef = Rc::new(Function::new(
+                        method.clone(),
+                        self.environment.clone(),
+                        false,
+                    ));
+                    let table = match method.kind {
+                        FunctionKind::Function => &mut mixin.methods,
+                        FunctionKind::Getter => &mut mixin.getters,
+                        FunctionKind::Setter => &mut mixin.setters,
+                    };
+                    table.insert(method.identifier.clone(), function);
+                }
+
+                self.define(trait_decl.name.clone(), Object::Trait(Rc::new(mixin)));
                 Flow::Ok(Object::Null)
             }
         }
@@ -147,18 +329,52 @@ impl Interpreter {
         match decl {
             Declaration::Statement(stmt) => Flow::Ok(self.execute_stmt(stmt)?),
             Declaration::Var {
-                identifier,
+                binding,
                 expression,
             } => {
                 let value = self.eval(expression);
-                self.environment
-                    .borrow_mut()
-                    .define(identifier.clone(), value);
+                self.bind(binding, value);
                 Flow::Ok(Object::Null)
             }
         }
     }
 
+    /// Defines the variables of `binding` in the current environment
+    pub(crate) fn bind(&mut self, binding: &Binding, value: Object) {
+        match binding {
+            Binding::Name(name) => self.define(name.clone(), value),
+            Binding::List { items, rest } => {
+                let values = destructure_list(&value, items.len(), rest.is_some());
+                for (item, value) in items.iter().zip(values.iter()) {
+                    self.bind(item, value.clone());
+                }
+                if let Some(rest) = rest {
+                    let rest_values = values[items.len()..].to_vec();
+                    self.define(rest.clone(), Object::new_list(rest_values));
+                }
+            }
+            Binding::Map { entries } => {
+                for (key, binding) in entries {
+                    let field = self.destructure_field(&value, key);
+                    self.bind(binding, field);
+                }
+            }
+        }
+    }
+
+    /// The value `{key}` destructures out of `value`, a map key or a property
+    pub(crate) fn destructure_field(&mut self, value: &Object, key: &Identifier) -> Object {
+        match value {
+            // Missing keys read as nil, like indexing does
+            Object::Map(map) => map
+                .borrow()
+                .get(key.name())
+                .cloned()
+                .unwrap_or(Object::Null),
+            object => self.get_property(object.clone(), key),
+        }
+    }
+
     pub fn execute_block(&mut self, statements: &[Declaration], env: EnvRef) -> Flow<Object> {
         let prev_env = self.environment.clone();
         self.environment = env;
@@ -178,42 +394,121 @@ impl Interpreter {
         Flow::Ok(last)
     }
 
+    pub(crate) fn environment(&self) -> EnvRef {
+        self.environment.clone()
+    }
+
+    pub(crate) fn set_environment(&mut self, env: EnvRef) {
+        self.environment = env;
+    }
+
+    /// Evaluates `expr` in the given environment instead of the current one
+    pub fn eval_in(&mut self, expr: &Expr, env: EnvRef) -> Object {
+        let prev_env = std::mem::replace(&mut self.environment, env);
+        let value = self.eval(expr);
+        self.environment = prev_env;
+        value
+    }
+
     pub fn eval(&mut self, expr: &Expr) -> Object {
         let expr_kind = &expr.kind;
         match expr_kind {
-            ExprKind::Binary { left, op, right } => self.eval_binary(left, op, right),
+            ExprKind::Binary { left, op, right } => {
+                let left = self.eval(left);
+                let right = self.eval(right);
+                self.binary(left, op.typ, right)
+            }
             ExprKind::Grouping { expr } => self.eval(expr),
             ExprKind::Literal { value } => self.eval_literal(value),
-            ExprKind::Unary { op, right } => self.eval_unary(op, right),
+            ExprKind::Unary { op, right } => {
+                let value = self.eval(right);
+                self.unary(op.typ, value)
+            }
             ExprKind::Var { name } => self.eval_var(name.clone(), expr),
-            ExprKind::Assign { name, expr } => self.eval_assign(name, expr),
+            ExprKind::Assign { name, expr: value } => self.eval_assign(name, value, expr),
             ExprKind::Logical { left, op, right } => self.eval_logical(left, op, right),
             ExprKind::Call {
                 callee,
                 parens: _,
                 args,
-            } => self.eval_call(callee, args),
+                named_args,
+            } => self.eval_call(callee, args, named_args),
             ExprKind::Get { name, object } => self.eval_get(name, object),
             ExprKind::Set {
                 object,
                 name,
                 value,
             } => self.eval_set(object, name, value),
-            ExprKind::This { token } => self.lookup_var(token.clone(), expr),
+            ExprKind::This { token } => self.lookup_var(token.clone(), expr.id),
+            ExprKind::Match {
+                keyword: _,
+                value,
+                arms,
+            } => {
+                let value = self.eval(value);
+                for arm in arms {
+                    if let Some(env) = self.match_pattern(&arm.pattern, &value) {
+                        let prev_env = std::mem::replace(&mut self.environment, env);
+                        let result = self.eval(&arm.body);
+                        self.environment = prev_env;
+                        return result;
+                    }
+                }
+                panic!("no match arm matched value {value}");
+            }
+            ExprKind::List { items } => self.eval_list(items),
+            ExprKind::Map { entries } => self.eval_map(entries),
+            ExprKind::Spread { expr: _ } => panic!("'{expr}' is only valid inside a list"),
+            ExprKind::Index { object, index } => {
+                let object = self.eval(object);
+                let index = self.eval(index);
+                self.get_index(object, index)
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                let object = self.eval(object);
+                let index = self.eval(index);
+                let value = self.eval(value);
+                self.set_index(object, index, value.clone());
+                value
+            }
+            ExprKind::Destructure { target, value } => {
+                let value = self.eval(value);
+                self.assign_target(target, value.clone());
+                value
+            }
             ExprKind::Super { token: _, method } => {
-                let distance = *self.locals.get(&expr.id).unwrap();
-                let Object::Class(superclass) =
-                    self.environment.borrow().get_at(distance, &"super".into())
-                else {
-                    panic!("bug: environment.get(super) did not return a class")
-                };
-                let Object::ClassInstance(object) = self
-                    .environment
-                    .borrow()
-                    .get_at(distance - 1, &"this".into())
-                else {
-                    panic!("bug: environment.get(this) did not return a class instance");
-                };
+                // `this` is in the scope right inside the one holding `super`
+                let local = *self.locals.get(&expr.id).unwrap();
+                let superclass = self.environment.borrow().get_at(local.depth, local.slot);
+                let object = self.environment.borrow().get_at(local.depth - 1, 0);
+                self.get_super(superclass, object, method)
+            }
+        }
+    }
+
+    /// `super.method`, looked up in `superclass` and bound to `object`,
+    /// which is `this` in the method using it
+    pub(crate) fn get_super(
+        &mut self,
+        superclass: Object,
+        object: Object,
+        method: &Identifier,
+    ) -> Object {
+        let Object::Class(superclass) = superclass else {
+            panic!("bug: environment.get(super) did not return a class")
+        };
+        match object {
+            Object::ClassInstance(object) => {
+                if let Some(getter) = superclass.find_getter(method) {
+                    let Object::Callable(getter) = getter.bind(&object) else {
+                        panic!("getter->bind did not return a callable, this is a bug");
+                    };
+                    return getter.call(self, &[]);
+                }
 
                 let method = superclass.find_method(method);
                 let Some(Object::Callable(method)) = method else {
@@ -221,30 +516,177 @@ impl Interpreter {
                 };
                 method.bind(&object)
             }
+            // 'super' inside a static method refers to the superclass' static methods
+            Object::Class(class) => {
+                let Some(method) = superclass.find_static_method(method) else {
+                    panic!("static method not found {method:?}");
+                };
+                method.bind_class(class)
+            }
+            _ => panic!("bug: environment.get(this) did not return a class or instance"),
+        }
+    }
+
+    /// Returns the environment holding the pattern's bindings if `value` matches it
+    pub(crate) fn match_pattern(&mut self, pattern: &Pattern, value: &Object) -> Option<EnvRef> {
+        let enumeration = match pattern {
+            Pattern::Variant {
+                enumeration, id, ..
+            } => {
+                let enumeration = enumeration
+                    .get()
+                    .expect("bug: variant pattern wasn't resolved");
+                self.lookup_var(enumeration.clone(), *id)
+            }
+            _ => Object::Null,
+        };
+        let bindings = self.pattern_bindings(pattern, &enumeration, value)?;
+        let env = Environment::new_ref(Some(self.environment.clone()));
+        for value in bindings {
+            env.borrow_mut().define(value);
         }
+        Some(env)
     }
 
-    fn eval_call(&mut self, callee: &Expr, args: &[Expr]) -> Object {
+    /// Returns the values the pattern binds, in order, if `value` matches it.
+    /// `enumeration` is the enum a variant pattern names, nil otherwise.
+    pub(crate) fn pattern_bindings(
+        &mut self,
+        pattern: &Pattern,
+        enumeration: &Object,
+        value: &Object,
+    ) -> Option<Vec<Object>> {
+        let bindings = match pattern {
+            Pattern::Wildcard => Vec::new(),
+            Pattern::Literal(literal) => {
+                if !self.is_equal(literal, value) {
+                    return None;
+                }
+                Vec::new()
+            }
+            Pattern::Variant { name, bindings, .. } => {
+                let Object::Enum(enumeration) = enumeration else {
+                    panic!("'{enumeration}' in pattern {pattern} is not an enum");
+                };
+                if !enumeration.variants.iter().any(|v| &v.name == name) {
+                    panic!("enum {} has no variant {name}", enumeration.name);
+                }
+                let Object::EnumValue(value) = value else {
+                    return None;
+                };
+                if !Rc::ptr_eq(enumeration, &value.enumeration) || &value.variant().name != name {
+                    return None;
+                }
+
+                match bindings {
+                    Some(bindings) => {
+                        if bindings.len() != value.values.len() {
+                            panic!(
+                                "pattern {pattern} binds {} fields but {} has {}",
+                                bindings.len(),
+                                value.variant().name,
+                                value.values.len()
+                            );
+                        }
+                        bindings
+                            .iter()
+                            .zip(&value.values)
+                            .filter(|(name, _)| **name != Identifier::WILDCARD)
+                            .map(|(_, value)| value.clone())
+                            .collect()
+                    }
+                    None => Vec::new(),
+                }
+            }
+        };
+        Some(bindings)
+    }
+
+    fn eval_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        named_args: &[(Identifier, Expr)],
+    ) -> Object {
+        let (callee, arguments, named_arguments) = self.eval_call_parts(callee, args, named_args);
+        self.call_object(callee, &arguments, &named_arguments)
+    }
+
+    /// Evaluates a call in tail position. Calls to functions are left for
+    /// the returning function to make in place of itself, see `Function::call`
+    fn eval_tail_call(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        named_args: &[(Identifier, Expr)],
+    ) -> Object {
+        let (callee, args, named) = self.eval_call_parts(callee, args, named_args);
+        if let Object::Callable(callable) = &callee {
+            if let Some(function) = callable.as_function() {
+                if !function.is_generator() {
+                    self.tail_call = Some(TailCall {
+                        function: function.clone(),
+                        args,
+                        named,
+                    });
+                    return Object::Null;
+                }
+            }
+        }
+        self.call_object(callee, &args, &named)
+    }
+
+    /// Evaluates the callee and the arguments of a call, checking the callee
+    /// accepts that many
+    fn eval_call_parts(
+        &mut self,
+        callee: &Expr,
+        args: &[Expr],
+        named_args: &[(Identifier, Expr)],
+    ) -> (Object, Vec<Object>, Vec<(Identifier, Object)>) {
         let callee = self.eval(callee);
 
         let arguments: Vec<_> = args.iter().map(|arg| self.eval(arg)).collect();
+        let named_arguments: Vec<_> = named_args
+            .iter()
+            .map(|(name, arg)| (name.clone(), self.eval(arg)))
+            .collect();
 
-        let callable = match callee {
-            Object::Callable(c) => c,
-            Object::Class(c) => c,
+        let arity = match &callee {
+            Object::Callable(c) => c.arity(),
+            Object::Class(c) => c.arity(),
             Object::String(_)
             | Object::Number(_)
             | Object::Boolean(_)
             | Object::ClassInstance(_)
+            | Object::Trait(_)
+            | Object::Enum(_)
+            | Object::EnumValue(_)
+            | Object::Generator(_)
+            | Object::Module(_)
+            | Object::List(_)
+            | Object::Map(_)
             | Object::Null => panic!("'{callee}' is not callable"),
         };
 
-        if callable.arity() != arguments.len() {
-            let arity = callable.arity();
-            let n_args = arguments.len();
+        let n_args = arguments.len() + named_arguments.len();
+        if !arity.accepts(n_args) {
             panic!("called fn/{arity} with {n_args}");
         }
-        callable.call(self, &arguments)
+        (callee, arguments, named_arguments)
+    }
+
+    fn call_object(
+        &mut self,
+        callee: Object,
+        args: &[Object],
+        named: &[(Identifier, Object)],
+    ) -> Object {
+        match callee {
+            Object::Class(class) => Class::instantiate(&class, self, args, named),
+            Object::Callable(c) => c.call_with_named(self, args, named),
+            _ => unreachable!(),
+        }
     }
 
     fn eval_logical(&mut self, left: &Expr, op: &Token, right: &Expr) -> Object {
@@ -265,25 +707,30 @@ impl Interpreter {
         value.clone()
     }
 
-    fn eval_unary(&mut self, op: &Token, right: &Expr) -> Object {
-        match op.typ {
-            TokenType::Minus => {
-                let sub = self.eval(right);
-                match sub {
-                    Object::Number(n) => Object::Number(-n),
-                    _ => panic!("invalid "),
-                }
-            }
+    pub(crate) fn unary(&mut self, op: TokenType, sub: Object) -> Object {
+        match op {
+            TokenType::Minus => match sub {
+                Object::Number(n) => Object::Number(-n),
+                sub => self
+                    .call_operator(&sub, &Identifier::NEG, &[])
+                    .unwrap_or_else(|| panic!("invalid operand '{sub:?}' for '-'")),
+            },
             t => {
                 panic!("unexpected token {t:?}. Expecting '-'")
             }
         }
     }
 
-    fn eval_binary(&mut self, left: &Expr, op: &Token, right: &Expr) -> Object {
-        let left = self.eval(left);
-        let right = self.eval(right);
-        match (left, op.typ, right) {
+    pub(crate) fn binary(&mut self, left: Object, op: TokenType, right: Object) -> Object {
+        if let Some(result) = self.eval_operator(&left, op, &right) {
+            return result;
+        }
+        if op == TokenType::Plus {
+            if let Some(result) = self.concat_instance(&left, &right) {
+                return result;
+            }
+        }
+        match (left, op, right) {
             // Numbers
             (Object::Number(left), TokenType::Minus, Object::Number(right)) => {
                 Object::Number(left - right)
@@ -309,12 +756,25 @@ impl Interpreter {
             (Object::Number(left), TokenType::LessEqual, Object::Number(right)) => {
                 Object::Boolean(left <= right)
             }
-            (left, TokenType::EqualEqual, right) => Object::Boolean(is_equal(left, right)),
-            (left, TokenType::BangEqual, right) => Object::Boolean(!is_equal(left, right)),
+            (left, TokenType::EqualEqual, right) => Object::Boolean(self.is_equal(&left, &right)),
+            (left, TokenType::BangEqual, right) => Object::Boolean(!self.is_equal(&left, &right)),
 
             (Object::String(left), TokenType::Plus, Object::String(right)) => {
                 Object::String(format!("{left}{right}").into())
             }
+            // Numbers are formatted the way `print` shows them, other values need `str()`
+            (Object::String(left), TokenType::Plus, Object::Number(right)) => {
+                Object::String(format!("{left}{right}").into())
+            }
+            (Object::Number(left), TokenType::Plus, Object::String(right)) => {
+                Object::String(format!("{left}{right}").into())
+            }
+            (left @ Object::String(_), TokenType::Plus, right)
+            | (left, TokenType::Plus, right @ Object::String(_)) => {
+                panic!(
+                    "can't concatenate '{left:?}' and '{right:?}', convert them with str() first"
+                )
+            }
 
             (left, op, right) => {
                 panic!(
@@ -324,64 +784,463 @@ impl Interpreter {
         }
     }
 
+    /// Dispatches arithmetic and ordering on class instances to their operator
+    /// methods. Orderings not defined by the class are derived from `__lt__`
+    /// and `__eq__`. When only the right operand is an instance, `1 < v` runs
+    /// as `v > 1`, and `+` and `*` are assumed to commute.
+    fn eval_operator(&mut self, left: &Object, op: TokenType, right: &Object) -> Option<Object> {
+        if !matches!(left, Object::ClassInstance(_)) {
+            let Object::ClassInstance(_) = right else {
+                return None;
+            };
+            let reflected = match op {
+                TokenType::Less => TokenType::Greater,
+                TokenType::LessEqual => TokenType::GreaterEqual,
+                TokenType::Greater => TokenType::Less,
+                TokenType::GreaterEqual => TokenType::LessEqual,
+                TokenType::Star => op,
+                // Adding an instance to a string concatenates it instead
+                TokenType::Plus if !matches!(left, Object::String(_)) => op,
+                _ => return None,
+            };
+            return self.eval_operator(right, reflected, left);
+        }
+
+        let args = [right.clone()];
+        let name = match op {
+            TokenType::Plus => Identifier::ADD,
+            TokenType::Minus => Identifier::SUB,
+            TokenType::Star => Identifier::MUL,
+            TokenType::Slash => Identifier::DIV,
+            TokenType::Less => Identifier::LT,
+            TokenType::LessEqual => Identifier::LE,
+            TokenType::Greater => Identifier::GT,
+            TokenType::GreaterEqual => Identifier::GE,
+            _ => return None,
+        };
+        if let Some(result) = self.call_operator(left, &name, &args) {
+            return Some(result);
+        }
+
+        let less = match op {
+            TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual => self.call_operator(left, &Identifier::LT, &args)?,
+            _ => return None,
+        };
+        let Object::Boolean(less) = less else {
+            panic!("operator method '__lt__' must return a boolean, got '{less:?}'");
+        };
+        let result = match op {
+            TokenType::Less => less,
+            TokenType::GreaterEqual => !less,
+            TokenType::LessEqual => less || self.is_equal(left, right),
+            _ => !less && !self.is_equal(left, right),
+        };
+        Some(Object::Boolean(result))
+    }
+
+    /// Concatenates a string with an instance that has a `toString()` method
+    fn concat_instance(&mut self, left: &Object, right: &Object) -> Option<Object> {
+        let s = match (left, right) {
+            (Object::String(left), right @ Object::ClassInstance(_)) => {
+                format!("{left}{}", self.call_to_string(right)?)
+            }
+            (left @ Object::ClassInstance(_), Object::String(right)) => {
+                format!("{}{right}", self.call_to_string(left)?)
+            }
+            _ => return None,
+        };
+        Some(Object::String(s.into()))
+    }
+
+    /// Calls the operator method `name` if `object` is an instance whose class defines it
+    fn call_operator(
+        &mut self,
+        object: &Object,
+        name: &Identifier,
+        args: &[Object],
+    ) -> Option<Object> {
+        let Object::ClassInstance(instance) = object else {
+            return None;
+        };
+        let Some(Object::Callable(method)) = instance.class.find_method(name) else {
+            return None;
+        };
+        if !method.arity().accepts(args.len()) {
+            panic!(
+                "operator method '{name}' of class {} must take {} argument(s), not {}",
+                instance.class.name,
+                args.len(),
+                method.arity()
+            );
+        }
+        let Object::Callable(method) = method.bind(instance) else {
+            panic!("method->bind did not return a callable, this is a bug");
+        };
+        Some(method.call(self, args))
+    }
+
+    /// Converts a value to the text `print`, `str()` and string concatenation
+    /// use. Instances can define it with a `toString()` (or `__str__`) method,
+    /// which also applies to instances inside lists and maps.
+    pub(crate) fn stringify(&mut self, value: &Object) -> String {
+        self.stringify_within(value, &mut Vec::new())
+    }
+
+    /// `path` holds the lists and maps being printed around `value`, one
+    /// that contains itself prints as `[...]` or `{...}` where it repeats
+    fn stringify_within(&mut self, value: &Object, path: &mut Vec<*const ()>) -> String {
+        if let Some(s) = self.call_to_string(value) {
+            return s;
+        }
+
+        match value {
+            Object::List(list) => {
+                let container = Rc::as_ptr(list).cast();
+                if path.contains(&container) {
+                    return "[...]".to_string();
+                }
+                path.push(container);
+                let items = list.borrow().clone();
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|item| self.stringify_nested(item, path))
+                    .collect();
+                path.pop();
+                format!("[{}]", items.join(", "))
+            }
+            Object::Map(map) => {
+                let container = Rc::as_ptr(map).cast();
+                if path.contains(&container) {
+                    return "{...}".to_string();
+                }
+                path.push(container);
+                let entries = map.borrow().clone();
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{key:?}: {}", self.stringify_nested(value, path)))
+                    .collect();
+                path.pop();
+                format!("{{{}}}", entries.join(", "))
+            }
+            value => value.to_string(),
+        }
+    }
+
+    /// Like `stringify` but strings are quoted, for values inside collections
+    fn stringify_nested(&mut self, value: &Object, path: &mut Vec<*const ()>) -> String {
+        match value {
+            Object::String(s) => format!("{s:?}"),
+            value => self.stringify_within(value, path),
+        }
+    }
+
+    /// Calls the `toString()` method of an instance that defines one
+    fn call_to_string(&mut self, value: &Object) -> Option<String> {
+        let (result, name) = [Identifier::TO_STRING, Identifier::STR]
+            .into_iter()
+            .find_map(|name| Some((self.call_operator(value, &name, &[])?, name)))?;
+        match result {
+            Object::String(s) => Some(s.to_string()),
+            other => panic!("{name}() must return a string, got '{other:?}'"),
+        }
+    }
+
+    /// Equality for `==` and match patterns. Instances can override it with
+    /// `__eq__`, otherwise they're only equal to themselves.
+    pub(crate) fn is_equal(&mut self, left: &Object, right: &Object) -> bool {
+        for (object, other) in [(left, right), (right, left)] {
+            match self.call_operator(object, &Identifier::EQ, std::slice::from_ref(other)) {
+                Some(Object::Boolean(result)) => return result,
+                Some(other) => panic!("__eq__ must return a boolean, got '{other:?}'"),
+                None => {}
+            }
+        }
+
+        match (left, right) {
+            (Object::String(left), Object::String(right)) => left == right,
+            (Object::Number(left), Object::Number(right)) => left == right,
+            (Object::Boolean(left), Object::Boolean(right)) => left == right,
+            (Object::Null, Object::Null) => true,
+            // Compared by data pointer, a method bound twice is two functions
+            (Object::Callable(left), Object::Callable(right)) => {
+                std::ptr::addr_eq(Rc::as_ptr(left), Rc::as_ptr(right))
+            }
+            (Object::Class(left), Object::Class(right)) => Rc::ptr_eq(left, right),
+            (Object::Trait(left), Object::Trait(right)) => Rc::ptr_eq(left, right),
+            (Object::ClassInstance(left), Object::ClassInstance(right)) => Rc::ptr_eq(left, right),
+            (Object::Enum(left), Object::Enum(right)) => Rc::ptr_eq(left, right),
+            (Object::EnumValue(left), Object::EnumValue(right)) => {
+                left.same_variant(right)
+                    && (left.values.iter())
+                        .zip(right.values.iter())
+                        .all(|(l, r)| self.is_equal(l, r))
+            }
+            (Object::Generator(left), Object::Generator(right)) => Rc::ptr_eq(left, right),
+            (Object::Module(left), Object::Module(right)) => Rc::ptr_eq(left, right),
+            // The same collection is equal to itself, even when it contains itself
+            (Object::Map(left), Object::Map(right)) if Rc::ptr_eq(left, right) => true,
+            (Object::List(left), Object::List(right)) if Rc::ptr_eq(left, right) => true,
+            (Object::Map(left), Object::Map(right)) => {
+                // Copied so `__eq__` methods are free to modify the maps
+                let left: Vec<_> = left.borrow().clone().into_iter().collect();
+                let right: Vec<_> = right.borrow().clone().into_iter().collect();
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right.iter())
+                        .all(|((lk, lv), (rk, rv))| lk == rk && self.is_equal(lv, rv))
+            }
+            (Object::List(left), Object::List(right)) => {
+                let (left, right) = (left.borrow().clone(), right.borrow().clone());
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right.iter())
+                        .all(|(l, r)| self.is_equal(l, r))
+            }
+            _ => false,
+        }
+    }
+
+    /// `object[index]`, instances can support it with `__index__`
+    pub(crate) fn get_index(&mut self, object: Object, index: Object) -> Object {
+        if let Some(value) =
+            self.call_operator(&object, &Identifier::INDEX, std::slice::from_ref(&index))
+        {
+            return value;
+        }
+
+        match (&object, &index) {
+            (Object::List(list), _) => {
+                let list = list.borrow();
+                list[list_index(&index, list.len())].clone()
+            }
+            (Object::String(s), _) => {
+                let chars: Vec<char> = s.chars().collect();
+                let c = chars[list_index(&index, chars.len())];
+                Object::String(c.to_string().into())
+            }
+            // Missing keys read as nil
+            (Object::Map(map), Object::String(key)) => {
+                map.borrow().get(key).cloned().unwrap_or(Object::Null)
+            }
+            (Object::Map(_), _) => panic!("map keys must be strings, got '{index:?}'"),
+            _ => panic!("'{object:?}' can't be indexed"),
+        }
+    }
+
+    /// `object[index] = value`, instances can support it with `__setindex__`
+    pub(crate) fn set_index(&mut self, object: Object, index: Object, value: Object) {
+        if self
+            .call_operator(
+                &object,
+                &Identifier::SET_INDEX,
+                &[index.clone(), value.clone()],
+            )
+            .is_some()
+        {
+            return;
+        }
+
+        match (&object, index) {
+            (Object::List(list), index) => {
+                let mut list = list.borrow_mut();
+                let i = list_index(&index, list.len());
+                list[i] = value;
+            }
+            (Object::Map(map), Object::String(key)) => {
+                map.borrow_mut().insert(key, value);
+            }
+            (Object::Map(_), index) => panic!("map keys must be strings, got '{index:?}'"),
+            _ => panic!("'{object:?}' doesn't support index assignment"),
+        }
+    }
+
     fn eval_var(&mut self, name: Identifier, expr: &Expr) -> Object {
-        self.lookup_var(name, expr)
+        self.lookup_var(name, expr.id)
     }
 
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.id, depth);
+    /// Records where the variable read or written by the expr with `id` lives
+    pub fn resolve(&mut self, id: u64, local: Local) {
+        self.locals.insert(id, local);
     }
 
-    fn lookup_var(&self, name: Identifier, expr: &Expr) -> Object {
-        if let Some(distance) = self.locals.get(&expr.id) {
-            return self.environment.borrow().get_at(*distance, &name);
+    /// Marks a call as the value of a `return` in a function, which doesn't
+    /// need a new frame
+    pub fn resolve_tail_call(&mut self, call: &Expr) {
+        self.tail_calls.insert(call.id);
+    }
+
+    /// Defines a variable in the current scope
+    fn define(&mut self, name: Identifier, value: Object) {
+        let mut env = self.environment.borrow_mut();
+        if env.is_global() {
+            self.globals.insert(name, value);
         } else {
-            return self.globals.borrow().get(&name).unwrap_or_else(|| {
-                panic!("could not find variable {name:?} in environment nor global scope. locals={:?}, environment={:?}, global={:?}", self.locals, self.environment, self.globals)
-            });
+            env.define(value);
         }
     }
 
-    fn eval_assign(&mut self, name: &Identifier, expr: &Expr) -> Object {
-        let value = self.eval(expr);
-        let distance = self.locals.get(&expr.id);
-        if let Some(distance) = distance {
+    fn lookup_var(&self, name: Identifier, id: u64) -> Object {
+        if let Some(local) = self.locals.get(&id) {
+            return self.environment.borrow().get_at(local.depth, local.slot);
+        }
+        self.get_global(&name)
+    }
+
+    pub(crate) fn get_global(&self, name: &Identifier) -> Object {
+        self.globals
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| panic!("Undefined variable '{name}'"))
+    }
+
+    pub(crate) fn define_global(&mut self, name: Identifier, value: Object) {
+        self.globals.insert(name, value);
+    }
+
+    pub(crate) fn assign_global(&mut self, name: &Identifier, value: Object) {
+        match self.globals.get_mut(name) {
+            Some(global) => *global = value,
+            None => panic!("Undefined variable '{name}'"),
+        }
+    }
+
+    fn eval_assign(&mut self, name: &Identifier, value: &Expr, expr: &Expr) -> Object {
+        let value = self.eval(value);
+        self.assign_var(name, expr, value.clone());
+        value
+    }
+
+    /// Assigns to a variable, `expr` is the expression the resolver resolved it for
+    fn assign_var(&mut self, name: &Identifier, expr: &Expr, value: Object) {
+        if let Some(local) = self.locals.get(&expr.id) {
             self.environment
-                .borrow()
-                .assign_at(*distance, name.clone(), value.clone());
+                .borrow_mut()
+                .assign_at(local.depth, local.slot, value);
         } else {
-            self.environment.borrow().mutate(name, value.clone());
+            self.assign_global(name, value);
         }
+    }
 
-        value
+    /// Assigns `value` to a target of a destructuring assignment
+    fn assign_target(&mut self, target: &Expr, value: Object) {
+        match &target.kind {
+            ExprKind::Var { name } => self.assign_var(name, target, value),
+            ExprKind::Get { object, name } => {
+                let object = self.eval(object);
+                self.set_property(object, name, value);
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.eval(object);
+                let index = self.eval(index);
+                self.set_index(object, index, value);
+            }
+            ExprKind::List { items } => {
+                let rest = items.last().and_then(|item| match &item.kind {
+                    ExprKind::Spread { expr } => Some(expr),
+                    _ => None,
+                });
+                let n_items = items.len() - rest.iter().len();
+                let values = destructure_list(&value, n_items, rest.is_some());
+                for (item, value) in items.iter().zip(values.iter()) {
+                    if let ExprKind::Spread { .. } = item.kind {
+                        break;
+                    }
+                    self.assign_target(item, value.clone());
+                }
+                if let Some(rest) = rest {
+                    let rest_values = values[n_items..].to_vec();
+                    self.assign_target(rest, Object::new_list(rest_values));
+                }
+            }
+            _ => panic!("bug: invalid assignment target '{target}'"),
+        }
     }
 
     fn eval_get(&mut self, name: &Identifier, object: &Expr) -> Object {
-        let obj = self.eval(object);
-        let Object::ClassInstance(ins) = obj else {
-            panic!("only instances have properties");
-        };
+        let object = self.eval(object);
+        self.get_property(object, name)
+    }
 
-        ins.get(name)
+    pub(crate) fn get_property(&mut self, object: Object, name: &Identifier) -> Object {
+        match object {
+            Object::ClassInstance(ins) => ins.get(self, name),
+            Object::Class(class) => Class::get(&class, name),
+            Object::Enum(enumeration) => Enum::get(&enumeration, name),
+            Object::EnumValue(value) => value.get(name),
+            Object::Generator(generator) => generator::get_method(&generator, name),
+            Object::String(s) => strings::get_method(&s, name),
+            Object::Module(module) => module.get(name),
+            _ => panic!(
+                "only instances, classes, enums, generators, strings and modules have properties"
+            ),
+        }
     }
 
     fn eval_set(&mut self, object: &Expr, name: &Identifier, value: &Expr) -> Object {
         let obj = self.eval(object);
-        let Object::ClassInstance(ins) = obj else {
-            panic!("only instances have fields");
-        };
         let value = self.eval(value);
-        ins.set(name.clone(), value.clone());
+        self.set_property(obj, name, value.clone());
         value
     }
+
+    pub(crate) fn set_property(&mut self, object: Object, name: &Identifier, value: Object) {
+        match object {
+            Object::ClassInstance(ins) => ins.set(self, name.clone(), value),
+            Object::Class(class) => class.set(name.clone(), value),
+            _ => panic!("only instances and classes have fields"),
+        }
+    }
+
+    fn eval_list(&mut self, items: &[Expr]) -> Object {
+        let mut values = Vec::with_capacity(items.len());
+        for item in items {
+            match &item.kind {
+                ExprKind::Spread { expr } => match self.eval(expr) {
+                    Object::List(list) => values.extend(list.borrow().iter().cloned()),
+                    other => panic!("can only spread lists, got '{other:?}'"),
+                },
+                _ => values.push(self.eval(item)),
+            }
+        }
+        Object::new_list(values)
+    }
+
+    fn eval_map(&mut self, entries: &[(Rc<str>, Expr)]) -> Object {
+        let mut map = BTreeMap::new();
+        for (key, value) in entries {
+            let value = self.eval(value);
+            map.insert(key.clone(), value);
+        }
+        Object::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+/// Returns the items of a list being destructured into `n_items` targets,
+/// checking that the lengths match unless there is a rest target
+pub(crate) fn destructure_list(value: &Object, n_items: usize, has_rest: bool) -> Vec<Object> {
+    let Object::List(list) = value else {
+        panic!("can only destructure lists with '[...]', got '{value:?}'");
+    };
+    let values = list.borrow().clone();
+    if values.len() < n_items || (!has_rest && values.len() != n_items) {
+        panic!(
+            "can't destructure a list of {} items into {n_items} variables",
+            values.len()
+        );
+    }
+    values
 }
 
-fn is_equal(left: Object, right: Object) -> bool {
-    match (left, right) {
-        (Object::String(left), Object::String(right)) => left == right,
-        (Object::Number(left), Object::Number(right)) => left == right,
-        (Object::Boolean(left), Object::Boolean(right)) => left == right,
-        (Object::Null, Object::Null) => true,
-        _ => false,
+/// Converts a number into an index in `0..len`
+fn list_index(index: &Object, len: usize) -> usize {
+    match index {
+        Object::Number(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => *n as usize,
+        Object::Number(n) => panic!("index {n} out of range for length {len}"),
+        other => panic!("list indexes must be numbers, got '{other:?}'"),
     }
 }
@@ -0,0 +1,145 @@
+use std::{
+    fs,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    builtins::{string_arg, Native},
+    interpreter::Interpreter,
+    types::{Arity, Object},
+};
+
+pub fn get_builtins() -> Vec<(&'static str, Object)> {
+    vec![
+        (
+            "readLine",
+            Native::object("readLine", Arity::exact(0), read_line),
+        ),
+        (
+            "readFile",
+            Native::object("readFile", Arity::exact(1), read_file),
+        ),
+        (
+            "writeFile",
+            Native::object("writeFile", Arity::exact(2), write_file),
+        ),
+        (
+            "appendFile",
+            Native::object("appendFile", Arity::exact(2), append_file),
+        ),
+        (
+            "listDir",
+            Native::object("listDir", Arity::exact(1), list_dir),
+        ),
+        ("exists", Native::object("exists", Arity::exact(1), exists)),
+    ]
+}
+
+/// Reads a line from stdin without its line ending, nil at the end of input
+fn read_line(_: &mut Interpreter, _: &[Object]) -> Object {
+    // Prompts printed so far should show up before waiting for input
+    let _ = std::io::stdout().flush();
+
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Object::Null,
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Object::String(line.into())
+        }
+        Err(e) => panic!("readLine() failed: {e}"),
+    }
+}
+
+/// Returns the path argument of a filesystem builtin, if scripts are allowed to use it
+fn path_arg<'a>(interpreter: &Interpreter, function: &str, args: &'a [Object]) -> &'a Path {
+    if !interpreter.host().filesystem {
+        panic!("{function}() can't be used, filesystem access is disabled");
+    }
+    Path::new(string_arg(function, args, 0).as_ref())
+}
+
+fn read_file(interpreter: &mut Interpreter, args: &[Object]) -> Object {
+    let path = path_arg(interpreter, "readFile", args);
+    match fs::read_to_string(path) {
+        Ok(contents) => Object::String(contents.into()),
+        Err(e) => panic!("readFile() can't read {}: {e}", path.display()),
+    }
+}
+
+fn write_file(interpreter: &mut Interpreter, args: &[Object]) -> Object {
+    let path = path_arg(interpreter, "writeFile", args);
+    let contents = string_arg("writeFile", args, 1);
+    if let Err(e) = fs::write(path, contents.as_bytes()) {
+        panic!("writeFile() can't write {}: {e}", path.display());
+    }
+    Object::Null
+}
+
+fn append_file(interpreter: &mut Interpreter, args: &[Object]) -> Object {
+    let path = path_arg(interpreter, "appendFile", args);
+    let contents = string_arg("appendFile", args, 1);
+    let result = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    if let Err(e) = result {
+        panic!("appendFile() can't write {}: {e}", path.display());
+    }
+    Object::Null
+}
+
+/// The names of the entries of a directory, sorted
+fn list_dir(interpreter: &mut Interpreter, args: &[Object]) -> Object {
+    let path = path_arg(interpreter, "listDir", args);
+    let entries = fs::read_dir(path).and_then(|entries| {
+        entries
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<std::io::Result<Vec<_>>>()
+    });
+    let mut names =
+        entries.unwrap_or_else(|e| panic!("listDir() can't list {}: {e}", path.display()));
+    names.sort();
+    Object::new_list(
+        names
+            .into_iter()
+            .map(|name| Object::String(name.into()))
+            .collect(),
+    )
+}
+
+fn exists(interpreter: &mut Interpreter, args: &[Object]) -> Object {
+    Object::Boolean(path_arg(interpreter, "exists", args).exists())
+}
+
+#[test]
+fn test_filesystem_disabled() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut interpreter = Interpreter::with_host(crate::host::Host {
+        filesystem: false,
+        ..Default::default()
+    });
+    let args = [
+        Object::String("Cargo.toml".into()),
+        Object::String("".into()),
+    ];
+    for (name, builtin) in get_builtins() {
+        let Object::Callable(builtin) = builtin else {
+            panic!("{name} isn't a function");
+        };
+        if name == "readLine" {
+            continue;
+        }
+        let args = &args[..builtin.arity().min];
+        let panic = catch_unwind(AssertUnwindSafe(|| builtin.call(&mut interpreter, args)))
+            .expect_err(name);
+        assert_eq!(
+            panic.downcast_ref::<String>().unwrap(),
+            &format!("{name}() can't be used, filesystem access is disabled")
+        );
+    }
+}
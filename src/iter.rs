@@ -0,0 +1,91 @@
+use std::{cell::RefCell, rc::Rc, vec};
+
+use crate::{
+    class::ClassInstance,
+    generator::Generator,
+    interpreter::Interpreter,
+    types::{Identifier, Object},
+};
+
+/// The state of a for-in loop over an iterable value.
+///
+/// Lists are walked by index so items appended during the loop are visited,
+/// map keys and string characters are collected up front. Instances follow
+/// the iterator protocol: `iterator()` returns an object with `hasNext()` and
+/// `next()` methods or a generator, and an object with those methods is its
+/// own iterator.
+/// Generators are resumed until they finish.
+pub enum Iter {
+    List {
+        list: Rc<RefCell<Vec<Object>>>,
+        next: usize,
+    },
+    Items(vec::IntoIter<Object>),
+    Protocol(Rc<ClassInstance>),
+    Generator(Rc<Generator>),
+}
+
+impl Iter {
+    pub fn new(interpreter: &mut Interpreter, iterable: Object) -> Self {
+        match iterable {
+            Object::List(list) => Iter::List { list, next: 0 },
+            Object::Map(map) => {
+                let keys: Vec<_> = map.borrow().keys().cloned().map(Object::String).collect();
+                Iter::Items(keys.into_iter())
+            }
+            Object::String(s) => {
+                let chars: Vec<_> = s
+                    .chars()
+                    .map(|c| Object::String(c.to_string().into()))
+                    .collect();
+                Iter::Items(chars.into_iter())
+            }
+            Object::ClassInstance(instance) => {
+                if instance.class.find_method(&Identifier::ITERATOR).is_none() {
+                    return Iter::Protocol(instance);
+                }
+                match call_method(interpreter, &instance, &Identifier::ITERATOR) {
+                    Object::ClassInstance(iterator) => Iter::Protocol(iterator),
+                    Object::Generator(generator) => Iter::Generator(generator),
+                    other => {
+                        panic!("iterator() must return an instance or a generator, got '{other:?}'")
+                    }
+                }
+            }
+            Object::Generator(generator) => Iter::Generator(generator),
+            other => panic!("'{other:?}' is not iterable"),
+        }
+    }
+
+    pub fn next(&mut self, interpreter: &mut Interpreter) -> Option<Object> {
+        match self {
+            Iter::List { list, next } => {
+                let item = list.borrow().get(*next).cloned();
+                *next += 1;
+                item
+            }
+            Iter::Items(items) => items.next(),
+            Iter::Protocol(iterator) => {
+                match call_method(interpreter, iterator, &Identifier::HAS_NEXT) {
+                    Object::Boolean(true) => {
+                        Some(call_method(interpreter, iterator, &Identifier::NEXT))
+                    }
+                    Object::Boolean(false) => None,
+                    other => panic!("hasNext() must return a boolean, got '{other:?}'"),
+                }
+            }
+            Iter::Generator(generator) => generator.resume(interpreter),
+        }
+    }
+}
+
+fn call_method(
+    interpreter: &mut Interpreter,
+    instance: &Rc<ClassInstance>,
+    name: &Identifier,
+) -> Object {
+    match instance.get(interpreter, name) {
+        Object::Callable(method) => method.call(interpreter, &[]),
+        other => panic!("'{name}' must be a method, got '{other:?}'"),
+    }
+}
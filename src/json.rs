@@ -0,0 +1,429 @@
+use std::{collections::BTreeMap, fmt::Write, iter::Peekable, rc::Rc, str::CharIndices};
+
+use crate::{
+    builtins::{number_arg, string_arg, Module, Native},
+    interner::Interner,
+    interpreter::Interpreter,
+    types::{Arity, Object},
+};
+
+pub fn module(interner: &Interner) -> Object {
+    Object::Module(Rc::new(Module::new(
+        interner,
+        "json",
+        vec![
+            ("parse", Native::object("parse", Arity::exact(1), parse)),
+            (
+                "stringify",
+                Native::object(
+                    "stringify",
+                    Arity {
+                        min: 1,
+                        max: Some(2),
+                    },
+                    stringify,
+                ),
+            ),
+        ],
+    )))
+}
+
+/// `json.parse(text)`, objects become maps and arrays become lists
+fn parse(_: &mut Interpreter, args: &[Object]) -> Object {
+    let text = string_arg("json.parse", args, 0);
+    let mut parser = JsonParser {
+        text,
+        chars: text.char_indices().peekable(),
+    };
+    parser.skip_whitespace();
+    let value = parser.value();
+    parser.skip_whitespace();
+    if parser.chars.peek().is_some() {
+        parser.error("unexpected data after the JSON value");
+    }
+    value
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl JsonParser<'_> {
+    /// Panics with the line and column of the next character
+    fn error(&mut self, message: &str) -> ! {
+        let offset = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+        let before = &self.text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        panic!("json.parse: {message} at line {line} column {column}")
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) {
+        if self.peek() != Some(expected) {
+            self.unexpected(&format!("'{expected}'"));
+        }
+        self.chars.next();
+    }
+
+    fn unexpected(&mut self, expected: &str) -> ! {
+        match self.peek() {
+            Some(c) => self.error(&format!("expected {expected} but found '{c}'")),
+            None => self.error(&format!("expected {expected} but the input ended")),
+        }
+    }
+
+    fn value(&mut self) -> Object {
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Object::String(self.string().into()),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.keyword("true", Object::Boolean(true)),
+            Some('f') => self.keyword("false", Object::Boolean(false)),
+            Some('n') => self.keyword("null", Object::Null),
+            _ => self.unexpected("a JSON value"),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Object) -> Object {
+        for expected in keyword.chars() {
+            if self.peek() != Some(expected) {
+                self.unexpected(&format!("'{keyword}'"));
+            }
+            self.chars.next();
+        }
+        value
+    }
+
+    fn object(&mut self) -> Object {
+        self.expect('{');
+        let mut map = BTreeMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.chars.next();
+            return Object::Map(Rc::new(map.into()));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                self.unexpected("a string key");
+            }
+            let key = self.string();
+            self.skip_whitespace();
+            self.expect(':');
+            self.skip_whitespace();
+            map.insert(key.into(), self.value());
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.chars.next();
+                }
+                Some('}') => {
+                    self.chars.next();
+                    return Object::Map(Rc::new(map.into()));
+                }
+                _ => self.unexpected("',' or '}'"),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Object {
+        self.expect('[');
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.chars.next();
+            return Object::new_list(items);
+        }
+
+        loop {
+            self.skip_whitespace();
+            items.push(self.value());
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.chars.next();
+                }
+                Some(']') => {
+                    self.chars.next();
+                    return Object::new_list(items);
+                }
+                _ => self.unexpected("',' or ']'"),
+            }
+        }
+    }
+
+    fn string(&mut self) -> String {
+        self.expect('"');
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.chars.next();
+                    return s;
+                }
+                Some('\\') => {
+                    self.chars.next();
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.chars.next();
+                            s.push(self.unicode_escape());
+                            continue;
+                        }
+                        _ => self.unexpected("an escape sequence"),
+                    };
+                    self.chars.next();
+                    s.push(escaped);
+                }
+                Some(c) if c < ' ' => self.error("control characters must be escaped in strings"),
+                Some(c) => {
+                    self.chars.next();
+                    s.push(c);
+                }
+                None => self.error("unterminated string"),
+            }
+        }
+    }
+
+    /// The code point of a `\u` escape, joining surrogate pairs
+    fn unicode_escape(&mut self) -> char {
+        let high = self.hex4();
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.peek() != Some('\\') {
+                self.error("expected a low surrogate after a high surrogate");
+            }
+            self.chars.next();
+            self.expect('u');
+            let low = self.hex4();
+            if !(0xDC00..0xE000).contains(&low) {
+                self.error("invalid low surrogate");
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).unwrap_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> u32 {
+        let mut code = 0;
+        for _ in 0..4 {
+            let Some(digit) = self.peek().and_then(|c| c.to_digit(16)) else {
+                self.unexpected("a hexadecimal digit");
+            };
+            self.chars.next();
+            code = code * 16 + digit;
+        }
+        code
+    }
+
+    fn number(&mut self) -> Object {
+        let start = self.chars.peek().map(|(i, _)| *i).unwrap();
+        if self.peek() == Some('-') {
+            self.chars.next();
+        }
+        match self.peek() {
+            Some('0') => {
+                self.chars.next();
+            }
+            Some('1'..='9') => self.digits(),
+            _ => self.unexpected("a digit"),
+        }
+        if self.peek() == Some('.') {
+            self.chars.next();
+            if !matches!(self.peek(), Some('0'..='9')) {
+                self.unexpected("a digit after '.'");
+            }
+            self.digits();
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.chars.next();
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.chars.next();
+            }
+            if !matches!(self.peek(), Some('0'..='9')) {
+                self.unexpected("a digit in the exponent");
+            }
+            self.digits();
+        }
+        let end = self.chars.peek().map_or(self.text.len(), |(i, _)| *i);
+        Object::Number(self.text[start..end].parse().unwrap())
+    }
+
+    fn digits(&mut self) {
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.chars.next();
+        }
+    }
+}
+
+/// `json.stringify(value, indent)`, pretty printed when `indent` is given.
+/// Instances are serialized from their fields.
+fn stringify(_: &mut Interpreter, args: &[Object]) -> Object {
+    let indent = match args.get(1) {
+        None | Some(Object::Null) => None,
+        Some(_) => {
+            let indent = number_arg("json.stringify", args, 1);
+            if indent.fract() != 0.0 || !(0.0..=10.0).contains(&indent) {
+                panic!("json.stringify: indent must be an integer between 0 and 10, got {indent}");
+            }
+            Some(indent as usize)
+        }
+    };
+    let mut writer = JsonWriter {
+        out: String::new(),
+        indent,
+        path: Vec::new(),
+    };
+    writer.value(&args[0], 0);
+    Object::String(writer.out.into())
+}
+
+struct JsonWriter {
+    out: String,
+    indent: Option<usize>,
+    /// The containers being written, to detect cycles
+    path: Vec<*const ()>,
+}
+
+impl JsonWriter {
+    fn value(&mut self, value: &Object, depth: usize) {
+        match value {
+            Object::Null => self.out.push_str("null"),
+            Object::Boolean(b) => write!(self.out, "{b}").unwrap(),
+            Object::Number(n) if n.is_finite() => write!(self.out, "{n}").unwrap(),
+            Object::Number(n) => panic!("json.stringify: {n} can't be represented in JSON"),
+            Object::String(s) => self.string(s),
+            Object::List(list) => {
+                let items = list.borrow().clone();
+                self.enter(Rc::as_ptr(list).cast());
+                self.container('[', ']', items.len(), depth, |writer, i| {
+                    writer.value(&items[i], depth + 1)
+                });
+                self.path.pop();
+            }
+            Object::Map(map) => {
+                let entries: Vec<_> = map.borrow().clone().into_iter().collect();
+                self.enter(Rc::as_ptr(map).cast());
+                self.object(&entries, depth);
+                self.path.pop();
+            }
+            Object::ClassInstance(instance) => {
+                let mut fields: Vec<_> = instance
+                    .fields
+                    .borrow()
+                    .iter()
+                    .map(|(name, value)| (Rc::<str>::from(name.name()), value.clone()))
+                    .collect();
+                fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                self.enter(Rc::as_ptr(&instance.fields).cast());
+                self.object(&fields, depth);
+                self.path.pop();
+            }
+            other => panic!("json.stringify: '{other}' can't be represented in JSON"),
+        }
+    }
+
+    fn enter(&mut self, container: *const ()) {
+        if self.path.contains(&container) {
+            panic!("json.stringify: can't serialize a value that contains itself");
+        }
+        self.path.push(container);
+    }
+
+    fn object(&mut self, entries: &[(Rc<str>, Object)], depth: usize) {
+        self.container('{', '}', entries.len(), depth, |writer, i| {
+            let (key, value) = &entries[i];
+            writer.string(key);
+            writer.out.push(':');
+            if writer.indent.is_some() {
+                writer.out.push(' ');
+            }
+            writer.value(value, depth + 1);
+        });
+    }
+
+    /// Writes `len` items between delimiters, one per line when indenting
+    fn container(
+        &mut self,
+        open: char,
+        close: char,
+        len: usize,
+        depth: usize,
+        mut item: impl FnMut(&mut Self, usize),
+    ) {
+        self.out.push(open);
+        for i in 0..len {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.newline(depth + 1);
+            item(self, i);
+        }
+        if len > 0 {
+            self.newline(depth);
+        }
+        self.out.push(close);
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if let Some(indent) = self.indent {
+            self.out.push('\n');
+            self.out.push_str(&" ".repeat(indent * depth));
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if c < ' ' => write!(self.out, "\\u{:04x}", c as u32).unwrap(),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+#[test]
+#[should_panic(expected = "json.parse: expected ',' or '}' but found ']' at line 2 column 8")]
+fn test_parse_error_position() {
+    let text = Object::String("{\"a\": 1,\n \"b\": 2]".into());
+    parse(&mut Interpreter::default(), &[text]);
+}
+
+#[test]
+#[should_panic(expected = "json.stringify: can't serialize a value that contains itself")]
+fn test_stringify_cycle() {
+    let list = Object::new_list(vec![Object::Number(1.0)]);
+    let Object::List(items) = &list else {
+        unreachable!()
+    };
+    items.borrow_mut().push(list.clone());
+    stringify(&mut Interpreter::default(), &[list]);
+}
@@ -0,0 +1,740 @@
+//! Compiled programs saved as `.loxc` files, so they can be run without
+//! parsing them again.
+//!
+//! A file starts with a header: the magic bytes `LOXC`, the format version,
+//! the length of the body and a checksum of it. The body is the script's
+//! function prototype: its name, parameters, upvalues, code, line table and
+//! constant pool, with the functions declared in it nested in the pool.
+//! Numbers are big endian, names and strings are UTF-8 prefixed with their
+//! length.
+//!
+//! Loading checks that every opcode is known, its operands are in the code,
+//! its constants exist and have the right kind, and jumps land on
+//! instructions. It doesn't follow the stack. The VM reports slots and value
+//! counts that reach past the current frame's stack as corrupt bytecode, but
+//! other nonsense, like popping values of the wrong kind, fails with the
+//! runtime error that value would cause.
+
+use std::{cell::OnceCell, rc::Rc};
+
+use thiserror::Error;
+
+use crate::{
+    chunk::{
+        Chunk, ClassProto, Constant, EnumProto, FunctionProto, OpCode, Parameter, TraitProto,
+        UpvalueRef,
+    },
+    interner::Interner,
+    syntax::{FunctionKind, Pattern},
+    types::{Identifier, Object},
+};
+
+const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bumped whenever the format or the instruction set changes
+pub const VERSION: u16 = 3;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4 + 4;
+
+/// How deep functions can be declared inside each other
+const MAX_NESTING: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum LoadError {
+    #[error("not a compiled Lox program")]
+    NotCompiled,
+    #[error("compiled for format version {found}, but this lox reads version {expected}, compile it again")]
+    Version { found: u16, expected: u16 },
+    #[error("the file is truncated")]
+    Truncated,
+    #[error("the file is corrupted, its checksum doesn't match")]
+    Checksum,
+    #[error("invalid bytecode: {0}")]
+    Invalid(String),
+}
+
+/// Encodes `script` with its header
+pub fn write(script: &FunctionProto) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_function(&mut body, script);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_be_bytes());
+    let len = u32::try_from(body.len()).expect("program too large to save");
+    bytes.extend_from_slice(&len.to_be_bytes());
+    bytes.extend_from_slice(&checksum(&body).to_be_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+/// Decodes and validates a script written by [`write`], interning its names
+/// with `interner`
+pub fn read(bytes: &[u8], interner: &Interner) -> Result<Rc<FunctionProto>, LoadError> {
+    if bytes.get(..MAGIC.len()) != Some(MAGIC) {
+        return Err(LoadError::NotCompiled);
+    }
+    let mut header = Reader::new(&bytes[MAGIC.len()..], interner);
+    let version = header.u16()?;
+    if version != VERSION {
+        return Err(LoadError::Version {
+            found: version,
+            expected: VERSION,
+        });
+    }
+    let len = header.u32()? as usize;
+    let sum = header.u32()?;
+    let body = &bytes[HEADER_LEN..];
+    if body.len() < len {
+        return Err(LoadError::Truncated);
+    } else if body.len() > len {
+        return invalid("unexpected bytes after the program");
+    }
+    if checksum(body) != sum {
+        return Err(LoadError::Checksum);
+    }
+
+    let mut reader = Reader::new(body, interner);
+    let script = reader.function(&[])?;
+    if !script.upvalues.is_empty() {
+        return invalid("the script can't capture upvalues");
+    }
+    if reader.pos != body.len() {
+        return invalid("unexpected bytes after the program");
+    }
+    Ok(Rc::new(script))
+}
+
+/// FNV-1a, enough to notice a damaged file
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn invalid<T>(message: impl Into<String>) -> Result<T, LoadError> {
+    Err(LoadError::Invalid(message.into()))
+}
+
+fn write_u16(out: &mut Vec<u8>, n: usize) {
+    let n = u16::try_from(n).expect("too many items to save");
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("too many items to save");
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_name(out: &mut Vec<u8>, name: &Identifier) {
+    write_str(out, name.name());
+}
+
+fn write_names(out: &mut Vec<u8>, names: &[Identifier]) {
+    write_u16(out, names.len());
+    for name in names {
+        write_name(out, name);
+    }
+}
+
+fn write_methods(out: &mut Vec<u8>, methods: &[(Identifier, FunctionKind)]) {
+    write_u16(out, methods.len());
+    for (name, kind) in methods {
+        write_name(out, name);
+        out.push(match *kind {
+            FunctionKind::Function => 0,
+            FunctionKind::Getter => 1,
+            FunctionKind::Setter => 2,
+        });
+    }
+}
+
+fn write_function(out: &mut Vec<u8>, function: &FunctionProto) {
+    write_name(out, &function.name);
+    write_u16(out, function.parameters.len());
+    for parameter in &function.parameters {
+        write_name(out, &parameter.name);
+        out.push(parameter.has_default as u8);
+    }
+    out.push(function.rest as u8);
+    write_u16(out, function.upvalues.len());
+    for upvalue in &function.upvalues {
+        out.push(upvalue.is_local as u8);
+        out.push(upvalue.index);
+    }
+
+    let chunk = &function.chunk;
+    write_u32(out, chunk.code.len());
+    out.extend_from_slice(&chunk.code);
+    write_u32(out, chunk.lines.len());
+    for &(offset, line) in &chunk.lines {
+        write_u32(out, offset);
+        write_u32(out, line);
+    }
+    write_u16(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        write_constant(out, constant);
+    }
+}
+
+fn write_constant(out: &mut Vec<u8>, constant: &Constant) {
+    match constant {
+        Constant::Number(n) => {
+            out.push(0);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+        Constant::String(s) => {
+            out.push(1);
+            write_str(out, s);
+        }
+        Constant::Name(name) => {
+            out.push(2);
+            write_name(out, name);
+        }
+        Constant::Names(names) => {
+            out.push(3);
+            write_names(out, names);
+        }
+        Constant::Keys(keys) => {
+            out.push(4);
+            write_u16(out, keys.len());
+            for key in keys {
+                write_str(out, key);
+            }
+        }
+        Constant::Function(function) => {
+            out.push(5);
+            write_function(out, function);
+        }
+        Constant::Class(class) => {
+            out.push(6);
+            write_name(out, &class.name);
+            out.push(class.has_superclass as u8);
+            out.push(class.traits);
+            write_methods(out, &class.methods);
+            write_names(out, &class.static_methods);
+        }
+        Constant::Trait(proto) => {
+            out.push(7);
+            write_name(out, &proto.name);
+            write_methods(out, &proto.methods);
+        }
+        Constant::Enum(proto) => {
+            out.push(8);
+            write_name(out, &proto.name);
+            write_u16(out, proto.variants.len());
+            for (name, fields) in &proto.variants {
+                write_name(out, name);
+                write_names(out, fields);
+            }
+        }
+        Constant::Pattern(pattern) => {
+            out.push(9);
+            write_pattern(out, pattern);
+        }
+    }
+}
+
+fn write_pattern(out: &mut Vec<u8>, pattern: &Pattern) {
+    match pattern {
+        Pattern::Wildcard => out.push(0),
+        Pattern::Literal(value) => {
+            out.push(1);
+            match value {
+                Object::Number(n) => {
+                    out.push(0);
+                    out.extend_from_slice(&n.to_be_bytes());
+                }
+                Object::String(s) => {
+                    out.push(1);
+                    write_str(out, s);
+                }
+                Object::Boolean(b) => out.push(2 + *b as u8),
+                Object::Null => out.push(4),
+                other => panic!("bug: '{other}' can't be a pattern"),
+            }
+        }
+        Pattern::Variant {
+            enumeration,
+            name,
+            bindings,
+            ..
+        } => {
+            out.push(2);
+            let enumeration = enumeration
+                .get()
+                .expect("bug: variant pattern wasn't resolved");
+            write_name(out, enumeration);
+            write_name(out, name);
+            match bindings {
+                Some(bindings) => {
+                    out.push(1);
+                    write_names(out, bindings);
+                }
+                None => out.push(0),
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    interner: &'a Interner,
+    pos: usize,
+    /// How many functions are being read, to stop on absurd nesting
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], interner: &'a Interner) -> Self {
+        Self {
+            bytes,
+            interner,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(n))
+            .ok_or(LoadError::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, LoadError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => invalid(format!("expected a flag, found {other}")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, LoadError> {
+        Ok(f64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<Rc<str>, LoadError> {
+        let len = self.u32()? as usize;
+        match std::str::from_utf8(self.bytes(len)?) {
+            Ok(s) => Ok(self.interner.intern_str(s)),
+            Err(_) => invalid("a string isn't valid UTF-8"),
+        }
+    }
+
+    fn name(&mut self) -> Result<Identifier, LoadError> {
+        Ok(self.interner.intern(&self.str()?))
+    }
+
+    /// Reads `count()` items with `item`
+    fn list<T>(
+        &mut self,
+        count: impl FnOnce(&mut Self) -> Result<usize, LoadError>,
+        mut item: impl FnMut(&mut Self) -> Result<T, LoadError>,
+    ) -> Result<Vec<T>, LoadError> {
+        let n = count(self)?;
+        (0..n).map(|_| item(self)).collect()
+    }
+
+    fn names(&mut self) -> Result<Vec<Identifier>, LoadError> {
+        self.list(|r| Ok(r.u16()? as usize), Self::name)
+    }
+
+    fn methods(&mut self) -> Result<Vec<(Identifier, FunctionKind)>, LoadError> {
+        self.list(
+            |r| Ok(r.u16()? as usize),
+            |r| {
+                let name = r.name()?;
+                let kind = match r.u8()? {
+                    0 => FunctionKind::Function,
+                    1 => FunctionKind::Getter,
+                    2 => FunctionKind::Setter,
+                    other => return invalid(format!("unknown method kind {other}")),
+                };
+                Ok((name, kind))
+            },
+        )
+    }
+
+    /// Reads a function declared in one with `enclosing` upvalues
+    fn function(&mut self, enclosing: &[UpvalueRef]) -> Result<FunctionProto, LoadError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return invalid("functions are nested too deeply");
+        }
+
+        let name = self.name()?;
+        let parameters = self.list(
+            |r| Ok(r.u16()? as usize),
+            |r| {
+                Ok(Parameter {
+                    name: r.name()?,
+                    has_default: r.bool()?,
+                })
+            },
+        )?;
+        let rest = self.bool()?;
+        let upvalues = self.list(
+            |r| Ok(r.u16()? as usize),
+            |r| {
+                Ok(UpvalueRef {
+                    is_local: r.bool()?,
+                    index: r.u8()?,
+                })
+            },
+        )?;
+        for upvalue in &upvalues {
+            if !upvalue.is_local && upvalue.index as usize >= enclosing.len() {
+                return invalid(format!(
+                    "'{name}' captures upvalue {} of a function with {}",
+                    upvalue.index,
+                    enclosing.len()
+                ));
+            }
+        }
+
+        let code_len = self.u32()? as usize;
+        let code = self.bytes(code_len)?.to_vec();
+        let lines = self.list(
+            |r| Ok(r.u32()? as usize),
+            |r| Ok((r.u32()? as usize, r.u32()? as usize)),
+        )?;
+        if !lines.windows(2).all(|pair| pair[0].0 < pair[1].0)
+            || lines.last().is_some_and(|&(offset, _)| offset >= code_len)
+        {
+            return invalid(format!("the line table of '{name}' is out of order"));
+        }
+        let constants = self.list(|r| Ok(r.u16()? as usize), |r| r.constant(&upvalues))?;
+
+        let function = FunctionProto {
+            name,
+            parameters,
+            rest,
+            upvalues,
+            chunk: Chunk {
+                code,
+                constants,
+                lines,
+            },
+        };
+        validate(&function)?;
+        self.depth -= 1;
+        Ok(function)
+    }
+
+    fn constant(&mut self, upvalues: &[UpvalueRef]) -> Result<Constant, LoadError> {
+        Ok(match self.u8()? {
+            0 => Constant::Number(self.f64()?),
+            1 => Constant::String(self.str()?),
+            2 => Constant::Name(self.name()?),
+            3 => Constant::Names(self.names()?),
+            4 => Constant::Keys(self.list(|r| Ok(r.u16()? as usize), Self::str)?),
+            5 => Constant::Function(Rc::new(self.function(upvalues)?)),
+            6 => Constant::Class(Rc::new(ClassProto {
+                name: self.name()?,
+                has_superclass: self.bool()?,
+                traits: self.u8()?,
+                methods: self.methods()?,
+                static_methods: self.names()?,
+            })),
+            7 => Constant::Trait(Rc::new(TraitProto {
+                name: self.name()?,
+                methods: self.methods()?,
+            })),
+            8 => Constant::Enum(Rc::new(EnumProto {
+                name: self.name()?,
+                variants: self.list(|r| Ok(r.u16()? as usize), |r| Ok((r.name()?, r.names()?)))?,
+            })),
+            9 => Constant::Pattern(self.pattern()?),
+            other => return invalid(format!("unknown constant tag {other}")),
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, LoadError> {
+        Ok(match self.u8()? {
+            0 => Pattern::Wildcard,
+            1 => Pattern::Literal(match self.u8()? {
+                0 => Object::Number(self.f64()?),
+                1 => Object::String(self.str()?),
+                2 => Object::Boolean(false),
+                3 => Object::Boolean(true),
+                4 => Object::Null,
+                other => return invalid(format!("unknown literal tag {other}")),
+            }),
+            // The VM reads the enum off the stack, it never looks at the id
+            2 => Pattern::Variant {
+                enumeration: OnceCell::from(self.name()?),
+                id: 0,
+                name: self.name()?,
+                bindings: self.bool()?.then(|| self.names()).transpose()?,
+            },
+            other => return invalid(format!("unknown pattern tag {other}")),
+        })
+    }
+}
+
+/// Checks that the VM can decode `function`'s code safely
+fn validate(function: &FunctionProto) -> Result<(), LoadError> {
+    let chunk = &function.chunk;
+    let name = &function.name;
+    let fail = |offset: usize, message: String| {
+        invalid(format!("{message} at offset {offset} in '{name}'"))
+    };
+
+    let mut starts = vec![false; chunk.code.len() + 1];
+    let mut jumps = Vec::new();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        starts[offset] = true;
+        let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+            return fail(offset, format!("unknown opcode {}", chunk.code[offset]));
+        };
+        let size = operand_size(op) + 1;
+        if offset + size > chunk.code.len() {
+            return fail(offset, format!("{op:?} is missing its operands"));
+        }
+        let byte = |i: usize| chunk.code[offset + i];
+        let short = |i: usize| chunk.read_u16(offset + i) as usize;
+        let constant = |i: usize| chunk.constants.get(short(i));
+
+        let expected = match op {
+            OpCode::Constant => {
+                matches!(constant(1), Some(Constant::Number(_) | Constant::String(_)))
+            }
+            OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Field => matches!(constant(1), Some(Constant::Name(_))),
+            OpCode::CallNamed => matches!(constant(2), Some(Constant::Names(_))),
+            OpCode::Closure => matches!(constant(1), Some(Constant::Function(_))),
+            OpCode::Class => matches!(constant(1), Some(Constant::Class(_))),
+            OpCode::Trait => matches!(constant(1), Some(Constant::Trait(_))),
+            OpCode::Enum => matches!(constant(1), Some(Constant::Enum(_))),
+            OpCode::Map => matches!(constant(1), Some(Constant::Keys(_))),
+            OpCode::Match => matches!(constant(1), Some(Constant::Pattern(_))),
+            _ => true,
+        };
+        if !expected {
+            return fail(
+                offset,
+                format!("{op:?} refers to a missing or wrong constant"),
+            );
+        }
+
+        match op {
+            OpCode::GetUpvalue | OpCode::SetUpvalue
+                if byte(1) as usize >= function.upvalues.len() =>
+            {
+                return fail(offset, format!("{op:?} refers to a missing upvalue"));
+            }
+            OpCode::JumpIfArg if byte(1) as usize >= function.parameters.len().min(64) => {
+                return fail(offset, format!("{op:?} refers to a missing parameter"));
+            }
+            OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::JumpIfFalsy
+            | OpCode::JumpIfTruthy
+            | OpCode::IterNext => jumps.push((offset, offset + size + short(1))),
+            OpCode::JumpIfArg => jumps.push((offset, offset + size + short(2))),
+            OpCode::Match => jumps.push((offset, offset + size + short(3))),
+            OpCode::Loop => match (offset + size).checked_sub(short(1)) {
+                Some(target) => jumps.push((offset, target)),
+                None => return fail(offset, "Loop jumps before the code".into()),
+            },
+            _ => {}
+        }
+        offset += size;
+    }
+
+    for (offset, target) in jumps {
+        if !starts.get(target).is_some_and(|&start| start) || target == chunk.code.len() {
+            return fail(offset, format!("jump to {target} misses an instruction"));
+        }
+    }
+    Ok(())
+}
+
+/// The number of operand bytes after `op`
+fn operand_size(op: OpCode) -> usize {
+    match op {
+        OpCode::Peek
+        | OpCode::Squash
+        | OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call
+        | OpCode::TailCall
+        | OpCode::RestList => 1,
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::SetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Jump
+        | OpCode::JumpIfFalse
+        | OpCode::JumpIfFalsy
+        | OpCode::JumpIfTruthy
+        | OpCode::Loop
+        | OpCode::Closure
+        | OpCode::Class
+        | OpCode::Trait
+        | OpCode::Enum
+        | OpCode::List
+        | OpCode::Map
+        | OpCode::IterNext
+        | OpCode::CheckList
+        | OpCode::Field => 2,
+        OpCode::JumpIfArg | OpCode::CallNamed => 3,
+        OpCode::Match => 4,
+        OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Pop
+        | OpCode::Dup
+        | OpCode::CloseUpvalue
+        | OpCode::GetIndex
+        | OpCode::SetIndex
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Negate
+        | OpCode::Not
+        | OpCode::Print
+        | OpCode::Return
+        | OpCode::Append
+        | OpCode::Extend
+        | OpCode::Iter
+        | OpCode::IterEnd
+        | OpCode::Generator
+        | OpCode::Yield
+        | OpCode::NoMatch => 0,
+    }
+}
+
+#[cfg(test)]
+fn compile_source(source: &str) -> Rc<FunctionProto> {
+    let (ast, _) = crate::resolver::resolve_source(source);
+    crate::compiler::compile(&ast)
+}
+
+#[test]
+fn test_round_trip() {
+    let script = compile_source(
+        "enum Shape { Circle(r), Dot }
+         class A { init(x) { this.x = x; } double { return this.x * 2; } }
+         fun f(a, b = 2, ...rest) {
+           var m = {\"k\": a};
+           match (Shape.Circle(a)) { Shape.Circle(r) => print r; _ => print \"no\"; }
+           fun g() { return a + b; }
+           return g;
+         }
+         print f(1)() + A(3).double;",
+    );
+    let bytes = write(&script);
+    let loaded = read(&bytes, &Interner::default()).unwrap();
+    assert_eq!(
+        crate::disasm::disassemble(&loaded),
+        crate::disasm::disassemble(&script)
+    );
+    assert_eq!(write(&loaded), bytes);
+}
+
+#[test]
+fn test_rejects_bad_files() {
+    let bytes = write(&compile_source("var x = 1; print x + 2;"));
+    assert!(matches!(
+        read(b"print 1;", &Interner::default()),
+        Err(LoadError::NotCompiled)
+    ));
+    assert!(matches!(
+        read(&bytes[..bytes.len() - 1], &Interner::default()),
+        Err(LoadError::Truncated)
+    ));
+
+    let mut old = bytes.clone();
+    old[5] = 0;
+    assert!(matches!(
+        read(&old, &Interner::default()),
+        Err(LoadError::Version {
+            found: 0,
+            expected: VERSION
+        })
+    ));
+
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        read(&corrupted, &Interner::default()),
+        Err(LoadError::Checksum)
+    ));
+
+    // Files with a valid checksum are still checked
+    let mut script = compile_source("print 1;");
+    Rc::get_mut(&mut script).unwrap().chunk.code[0] = u8::MAX;
+    assert!(matches!(
+        read(&write(&script), &Interner::default()),
+        Err(LoadError::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_forged_stack_operands() {
+    use OpCode::*;
+
+    let cases: [(Vec<u8>, &str); 6] = [
+        (vec![GetLocal as u8, 200], "GetLocal 200"),
+        (vec![Nil as u8, SetLocal as u8, 200], "SetLocal 200"),
+        (vec![Peek as u8, 9], "Peek 10"),
+        (vec![Nil as u8, Squash as u8, 5], "Squash 5"),
+        (vec![List as u8, 1, 0], "List 256"),
+        (vec![Call as u8, 200], "Call 201"),
+    ];
+    for (mut code, message) in cases {
+        code.extend([Nil as u8, Return as u8]);
+        let mut script = compile_source("print 1;");
+        Rc::get_mut(&mut script).unwrap().chunk.code = code;
+        let mut interpreter = crate::interpreter::Interpreter::default();
+        let script = read(&write(&script), interpreter.interner()).unwrap();
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            crate::vm::interpret(&mut interpreter, script)
+        }))
+        .expect_err(message);
+        let panic = panic.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            *panic,
+            format!("corrupt bytecode: {message} is out of range of the stack")
+        );
+    }
+}
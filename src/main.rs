@@ -1,45 +1,251 @@
-use std::path::Path;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use clap::Parser;
-use eyre::Result;
+use chunk::FunctionProto;
+use clap::{Parser, Subcommand, ValueEnum};
+use eyre::{bail, Result, WrapErr};
 
 mod builtins;
 mod callable;
+mod chunk;
 mod class;
+mod compiler;
+mod disasm;
+mod enums;
 mod environment;
 mod eval;
+mod generator;
+mod host;
+mod interner;
 mod interpreter;
+mod io;
+mod iter;
+mod json;
+mod loxc;
+mod optimizer;
+mod process;
 mod resolver;
 mod scanner;
+mod strings;
 mod syntax;
 mod types;
+mod vm;
+use host::{Clock, Host, Rng};
+use interner::Interner;
 use interpreter::Interpreter;
 use resolver::Resolver;
-use scanner::Scanner;
+use syntax::Program;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    filename: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The script to run
+    #[arg(required = true)]
+    filename: Option<String>,
+
+    /// How to run the script, `tree` unless `--trace` is given
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+
+    /// Don't fold constants or remove dead code before running
+    #[arg(long)]
+    no_opt: bool,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+/// Options for running a script, whether from source or compiled
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Arguments for the script, returned by `args()`. Everything after the
+    /// script is passed to it, options like `--no-fs` included, so options
+    /// for lox go before the script.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    script_args: Vec<String>,
+
+    /// Don't let the script read or write files
+    #[arg(long)]
+    no_fs: bool,
+
+    /// Seed for random() and randomInt(), so runs are reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Make clock() always return this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    fixed_clock: Option<f64>,
+
+    /// Print the VM stack and each instruction before running it, implies
+    /// `--backend vm` and can't be used with `--backend tree`
+    #[arg(long)]
+    trace: bool,
+}
+
+impl RunArgs {
+    fn host(self) -> Host {
+        Host {
+            filesystem: !self.no_fs,
+            args: self.script_args,
+            clock: self.fixed_clock.map_or(Clock::System, Clock::Fixed),
+            rng: self.seed.map_or_else(Rng::from_time, Rng::seeded),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the bytecode a script compiles to
+    Disasm {
+        filename: String,
+        /// Don't fold constants or remove dead code before compiling
+        #[arg(long)]
+        no_opt: bool,
+    },
+    /// Compile a script to bytecode that `lox run` can load without parsing it
+    Compile {
+        filename: String,
+        /// Where to write the bytecode, the script's name with a `.loxc`
+        /// extension by default
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Don't fold constants or remove dead code before compiling
+        #[arg(long)]
+        no_opt: bool,
+    },
+    /// Run a script compiled by `lox compile`
+    Run {
+        filename: String,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Walk the syntax tree
+    Tree,
+    /// Compile to bytecode and run it on a stack virtual machine
+    Vm,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    match args.command {
+        Some(Command::Disasm { filename, no_opt }) => {
+            print!(
+                "{}",
+                disasm::disassemble(&*compile_file(filename, !no_opt)?)
+            );
+            return Ok(());
+        }
+        Some(Command::Compile {
+            filename,
+            output,
+            no_opt,
+        }) => {
+            let output = output.map_or_else(
+                || Path::new(&filename).with_extension("loxc"),
+                PathBuf::from,
+            );
+            let script = compile_file(&filename, !no_opt)?;
+            std::fs::write(&output, loxc::write(&script))
+                .wrap_err_with(|| format!("writing {}", output.display()))?;
+            return Ok(());
+        }
+        Some(Command::Run { filename, run }) => {
+            let bytes = std::fs::read(&filename).wrap_err_with(|| format!("reading {filename}"))?;
+            let trace = run.trace;
+            let mut interpreter = Interpreter::with_host(run.host());
+            let script = loxc::read(&bytes, interpreter.interner())
+                .wrap_err_with(|| format!("loading {filename}"))?;
+            interpreter.vm.trace = trace;
+            return exit(vm::interpret(&mut interpreter, script));
+        }
+        None => {}
+    }
 
-    let input_file = Path::new(&args.filename);
+    let filename = args.filename.expect("the filename is required");
+    let input_file = Path::new(&filename);
+    let backend = match (args.backend, args.run.trace) {
+        (Some(Backend::Tree), true) => bail!("--trace can't be used with --backend tree"),
+        (Some(backend), _) => backend,
+        (None, true) => Backend::Vm,
+        (None, false) => Backend::Tree,
+    };
+    let trace = args.run.trace;
+    exit(run_file(
+        input_file,
+        args.run.host(),
+        backend,
+        trace,
+        !args.no_opt,
+    )?)
+}
 
-    run_file(input_file)?;
+/// Ends the process with the status the script passed to `exit()`
+fn exit(status: i32) -> Result<()> {
+    if status != 0 {
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        std::process::exit(status);
+    }
     Ok(())
 }
 
-fn run_file<P: AsRef<Path>>(input_file: P) -> Result<()> {
+fn parse_file<P: AsRef<Path>>(input_file: P, interner: &Interner) -> Result<Program> {
     let contents = std::fs::read_to_string(input_file)?;
-    let scanner = Scanner::new(&contents);
-    let mut parser = syntax::Parser::new(scanner.scan_tokens().map(|t| t.unwrap()));
-    let ast = parser.parse();
-    let resolver = Resolver::new(Interpreter::new());
-    let mut interpreter = resolver.run(&ast);
-    interpreter.interpret(ast);
+    Ok(syntax::parse_source(&contents, interner))
+}
 
-    Ok(())
+/// Parses and compiles a script for the `vm` backend
+fn compile_file<P: AsRef<Path>>(input_file: P, optimize: bool) -> Result<Rc<FunctionProto>> {
+    let interpreter = Interpreter::default();
+    let ast = parse_file(input_file, interpreter.interner())?;
+    Resolver::new(interpreter).run(&ast);
+    let ast = if optimize {
+        optimizer::optimize(ast)
+    } else {
+        ast
+    };
+    Ok(compiler::compile(&ast))
+}
+
+fn run_file<P: AsRef<Path>>(
+    input_file: P,
+    host: Host,
+    backend: Backend,
+    trace: bool,
+    optimize: bool,
+) -> Result<i32> {
+    let interpreter = Interpreter::with_host(host);
+    let ast = parse_file(input_file, interpreter.interner())?;
+    let mut interpreter = Resolver::new(interpreter).run(&ast);
+    let ast = if optimize {
+        optimizer::optimize(ast)
+    } else {
+        ast
+    };
+    let status = match backend {
+        Backend::Tree => interpreter.interpret(ast),
+        Backend::Vm => {
+            interpreter.vm.trace = trace;
+            let script = compiler::compile(&ast);
+            vm::interpret(&mut interpreter, script)
+        }
+    };
+    Ok(status)
 }
@@ -0,0 +1,427 @@
+//! Simplifies the AST before it runs: operations on literals are folded into
+//! their result, and branches, loops and statements that can never run are
+//! dropped.
+//!
+//! It runs after the resolver, so errors are still reported in dead code.
+//! Expressions that aren't replaced keep their id, which is how the
+//! interpreter finds the variables the resolver resolved. Anything that would
+//! fail at runtime, like `1 + true`, is left for the runtime to report.
+
+use std::rc::Rc;
+
+use crate::{
+    scanner::TokenType,
+    syntax::{
+        ClassDecl, Declaration, Expr, ExprKind, FunctionStmt, MatchArm, Parameter, Program, Stmt,
+        TraitDecl,
+    },
+    types::Object,
+};
+
+pub fn optimize(program: Program) -> Program {
+    let Program::Declarations(declarations) = program;
+    Program::Declarations(optimize_block(declarations))
+}
+
+/// Optimizes each declaration, dropping the ones after a statement that
+/// always returns
+fn optimize_block(declarations: Vec<Declaration>) -> Vec<Declaration> {
+    let mut optimized = Vec::with_capacity(declarations.len());
+    for declaration in declarations {
+        let declaration = match declaration {
+            Declaration::Var {
+                binding,
+                expression,
+            } => Declaration::Var {
+                binding,
+                expression: optimize_expr(expression),
+            },
+            Declaration::Statement(stmt) => Declaration::Statement(optimize_stmt(stmt)),
+        };
+        let returns = matches!(&declaration, Declaration::Statement(stmt) if always_returns(stmt));
+        optimized.push(declaration);
+        if returns {
+            break;
+        }
+    }
+    optimized
+}
+
+/// Whether running `stmt` never goes on to the next statement
+fn always_returns(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return { .. } => true,
+        Stmt::Block(declarations) => declarations
+            .iter()
+            .any(|decl| matches!(decl, Declaration::Statement(stmt) if always_returns(stmt))),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(then_branch) && always_returns(else_branch),
+        // A value no arm matches is an error, so it doesn't go on either
+        Stmt::Match { arms, .. } => arms.iter().all(|arm| always_returns(&arm.body)),
+        _ => false,
+    }
+}
+
+/// A statement that does nothing and completes with `nil`, like a skipped `if`
+fn empty() -> Stmt {
+    Stmt::Block(Vec::new())
+}
+
+fn optimize_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Expr(expr) => Stmt::Expr(optimize_expr(expr)),
+        Stmt::FunctionDecl(function) => Stmt::FunctionDecl(optimize_function(function)),
+        Stmt::ClassDecl(class) => Stmt::ClassDecl(ClassDecl {
+            name: class.name,
+            methods: class.methods.into_iter().map(optimize_function).collect(),
+            static_methods: class
+                .static_methods
+                .into_iter()
+                .map(optimize_function)
+                .collect(),
+            static_fields: class
+                .static_fields
+                .into_iter()
+                .map(|(name, value)| (name, optimize_expr(value)))
+                .collect(),
+            superclass: class.superclass.map(optimize_expr),
+            traits: class.traits.into_iter().map(optimize_expr).collect(),
+        }),
+        Stmt::TraitDecl(mixin) => Stmt::TraitDecl(TraitDecl {
+            name: mixin.name,
+            methods: mixin.methods.into_iter().map(optimize_function).collect(),
+        }),
+        stmt @ Stmt::EnumDecl(_) => stmt,
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let condition = optimize_expr(condition);
+            let then_branch = optimize_stmt(*then_branch);
+            let else_branch = else_branch.map(|branch| optimize_stmt(*branch));
+            match condition.kind {
+                ExprKind::Literal {
+                    value: Object::Boolean(true),
+                } => then_branch,
+                ExprKind::Literal {
+                    value: Object::Boolean(false),
+                } => else_branch.unwrap_or_else(empty),
+                _ => Stmt::If {
+                    condition,
+                    then_branch: Box::new(then_branch),
+                    else_branch: else_branch.map(Box::new),
+                },
+            }
+        }
+        Stmt::Print(expr) => Stmt::Print(optimize_expr(expr)),
+        Stmt::Return { value } => Stmt::Return {
+            value: optimize_expr(value),
+        },
+        Stmt::Yield { keyword, value } => Stmt::Yield {
+            keyword,
+            value: optimize_expr(value),
+        },
+        Stmt::While { condition, body } => {
+            let condition = optimize_expr(condition);
+            if let ExprKind::Literal {
+                value: Object::Boolean(false),
+            } = condition.kind
+            {
+                return empty();
+            }
+            Stmt::While {
+                condition,
+                body: Box::new(optimize_stmt(*body)),
+            }
+        }
+        Stmt::ForIn {
+            binding,
+            iterable,
+            body,
+        } => Stmt::ForIn {
+            binding,
+            iterable: optimize_expr(iterable),
+            body: Box::new(optimize_stmt(*body)),
+        },
+        Stmt::Block(declarations) => Stmt::Block(optimize_block(declarations)),
+        Stmt::Match {
+            keyword,
+            value,
+            arms,
+        } => Stmt::Match {
+            keyword,
+            value: optimize_expr(value),
+            arms: arms
+                .into_iter()
+                .map(|arm| MatchArm {
+                    pattern: arm.pattern,
+                    body: optimize_stmt(arm.body),
+                })
+                .collect(),
+        },
+    }
+}
+
+fn optimize_function(function: Rc<FunctionStmt>) -> Rc<FunctionStmt> {
+    let function = Rc::unwrap_or_clone(function);
+    Rc::new(FunctionStmt {
+        parameters: function
+            .parameters
+            .into_iter()
+            .map(|parameter| Parameter {
+                default: parameter.default.map(optimize_expr),
+                ..parameter
+            })
+            .collect(),
+        body: optimize_block(function.body),
+        ..function
+    })
+}
+
+fn optimize_expr(expr: Expr) -> Expr {
+    let Expr { id, line, kind } = expr;
+    let kind = match kind {
+        ExprKind::Binary { left, op, right } => {
+            let (left, right) = (
+                Box::new(optimize_expr(*left)),
+                Box::new(optimize_expr(*right)),
+            );
+            match fold_binary(&left, op.typ, &right) {
+                Some(value) => ExprKind::Literal { value },
+                None => ExprKind::Binary { left, op, right },
+            }
+        }
+        ExprKind::Unary { op, right } => {
+            let right = Box::new(optimize_expr(*right));
+            match (op.typ, &right.kind) {
+                (
+                    TokenType::Minus,
+                    ExprKind::Literal {
+                        value: Object::Number(n),
+                    },
+                ) => ExprKind::Literal {
+                    value: Object::Number(-n),
+                },
+                _ => ExprKind::Unary { op, right },
+            }
+        }
+        ExprKind::Grouping { expr } => {
+            let expr = optimize_expr(*expr);
+            if let ExprKind::Literal { .. } = expr.kind {
+                return expr;
+            }
+            ExprKind::Grouping {
+                expr: Box::new(expr),
+            }
+        }
+        ExprKind::Logical { left, op, right } => {
+            let (left, right) = (
+                Box::new(optimize_expr(*left)),
+                Box::new(optimize_expr(*right)),
+            );
+            // `or` keeps a true left side and `and` a false one, otherwise the
+            // value is the right side's, whatever it is
+            if let ExprKind::Literal {
+                value: Object::Boolean(value),
+            } = left.kind
+            {
+                return if value == (op.typ == TokenType::Or) {
+                    *left
+                } else {
+                    *right
+                };
+            }
+            ExprKind::Logical { left, op, right }
+        }
+        ExprKind::Assign { name, expr } => ExprKind::Assign {
+            name,
+            expr: Box::new(optimize_expr(*expr)),
+        },
+        ExprKind::Call {
+            callee,
+            parens,
+            args,
+            named_args,
+        } => ExprKind::Call {
+            callee: Box::new(optimize_expr(*callee)),
+            parens,
+            args: args.into_iter().map(optimize_expr).collect(),
+            named_args: named_args
+                .into_iter()
+                .map(|(name, arg)| (name, optimize_expr(arg)))
+                .collect(),
+        },
+        ExprKind::Get { name, object } => ExprKind::Get {
+            name,
+            object: Box::new(optimize_expr(*object)),
+        },
+        ExprKind::Set {
+            object,
+            name,
+            value,
+        } => ExprKind::Set {
+            object: Box::new(optimize_expr(*object)),
+            name,
+            value: Box::new(optimize_expr(*value)),
+        },
+        ExprKind::Match {
+            keyword,
+            value,
+            arms,
+        } => ExprKind::Match {
+            keyword,
+            value: Box::new(optimize_expr(*value)),
+            arms: arms
+                .into_iter()
+                .map(|arm| MatchArm {
+                    pattern: arm.pattern,
+                    body: optimize_expr(arm.body),
+                })
+                .collect(),
+        },
+        ExprKind::List { items } => ExprKind::List {
+            items: items.into_iter().map(optimize_expr).collect(),
+        },
+        ExprKind::Map { entries } => ExprKind::Map {
+            entries: entries
+                .into_iter()
+                .map(|(key, value)| (key, optimize_expr(value)))
+                .collect(),
+        },
+        ExprKind::Spread { expr } => ExprKind::Spread {
+            expr: Box::new(optimize_expr(*expr)),
+        },
+        ExprKind::Index { object, index } => ExprKind::Index {
+            object: Box::new(optimize_expr(*object)),
+            index: Box::new(optimize_expr(*index)),
+        },
+        ExprKind::SetIndex {
+            object,
+            index,
+            value,
+        } => ExprKind::SetIndex {
+            object: Box::new(optimize_expr(*object)),
+            index: Box::new(optimize_expr(*index)),
+            value: Box::new(optimize_expr(*value)),
+        },
+        // The target is assigned to, not evaluated
+        ExprKind::Destructure { target, value } => ExprKind::Destructure {
+            target,
+            value: Box::new(optimize_expr(*value)),
+        },
+        kind @ (ExprKind::Literal { .. }
+        | ExprKind::This { .. }
+        | ExprKind::Super { .. }
+        | ExprKind::Var { .. }) => kind,
+    };
+    Expr { id, line, kind }
+}
+
+/// The value of `left op right` when both are literals and the operation
+/// can't fail, the same value `Interpreter::binary` gives
+fn fold_binary(left: &Expr, op: TokenType, right: &Expr) -> Option<Object> {
+    let (ExprKind::Literal { value: left }, ExprKind::Literal { value: right }) =
+        (&left.kind, &right.kind)
+    else {
+        return None;
+    };
+    Some(match (left, op, right) {
+        (left, TokenType::EqualEqual, right) => Object::Boolean(literals_equal(left, right)?),
+        (left, TokenType::BangEqual, right) => Object::Boolean(!literals_equal(left, right)?),
+        (Object::Number(left), op, Object::Number(right)) => match op {
+            TokenType::Plus => Object::Number(left + right),
+            TokenType::Minus => Object::Number(left - right),
+            TokenType::Star => Object::Number(left * right),
+            TokenType::Slash => Object::Number(left / right),
+            TokenType::Greater => Object::Boolean(left > right),
+            TokenType::GreaterEqual => Object::Boolean(left >= right),
+            TokenType::Less => Object::Boolean(left < right),
+            TokenType::LessEqual => Object::Boolean(left <= right),
+            _ => return None,
+        },
+        (Object::String(left), TokenType::Plus, Object::String(right)) => {
+            Object::String(format!("{left}{right}").into())
+        }
+        (Object::String(left), TokenType::Plus, Object::Number(right)) => {
+            Object::String(format!("{left}{right}").into())
+        }
+        (Object::Number(left), TokenType::Plus, Object::String(right)) => {
+            Object::String(format!("{left}{right}").into())
+        }
+        _ => return None,
+    })
+}
+
+/// Whether two literals are equal, `None` unless both are simple values
+fn literals_equal(left: &Object, right: &Object) -> Option<bool> {
+    Some(match (left, right) {
+        (Object::String(left), Object::String(right)) => left == right,
+        (Object::Number(left), Object::Number(right)) => left == right,
+        (Object::Boolean(left), Object::Boolean(right)) => left == right,
+        (Object::Null, Object::Null) => true,
+        (
+            Object::String(_) | Object::Number(_) | Object::Boolean(_) | Object::Null,
+            Object::String(_) | Object::Number(_) | Object::Boolean(_) | Object::Null,
+        ) => false,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+fn optimize_source(source: &str) -> Vec<Declaration> {
+    let interner = crate::interner::Interner::default();
+    let Program::Declarations(declarations) =
+        optimize(crate::syntax::parse_source(source, &interner));
+    declarations
+}
+
+#[test]
+fn test_fold_constants() {
+    let declarations = optimize_source("print -(1 + 2 * 3) + 1; print \"a\" + 1 == \"a1\";");
+    let printed: Vec<_> = declarations
+        .iter()
+        .map(|decl| match decl {
+            Declaration::Statement(Stmt::Print(Expr {
+                kind: ExprKind::Literal { value },
+                ..
+            })) => value.to_string(),
+            other => panic!("not folded: {other:?}"),
+        })
+        .collect();
+    assert_eq!(printed, ["-6", "true"]);
+
+    // Errors are left for the runtime
+    let declarations = optimize_source("print 1 + true;");
+    assert!(matches!(
+        &declarations[0],
+        Declaration::Statement(Stmt::Print(Expr {
+            kind: ExprKind::Binary { .. },
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_remove_dead_code() {
+    let declarations = optimize_source(
+        "fun f(x) { if (false or 1 > 2) print 1; else { return x; print 2; } print 3; }
+         while (!true == false and false) print 4;",
+    );
+    let Declaration::Statement(Stmt::FunctionDecl(function)) = &declarations[0] else {
+        panic!("expected a function");
+    };
+    // The `if` became its else branch, which returns, so `print 3` is gone too
+    assert!(matches!(
+        &function.body[..],
+        [Declaration::Statement(Stmt::Block(body))] if body.len() == 1
+    ));
+    // `!` isn't folded, so the loop stays
+    assert!(matches!(
+        &declarations[1],
+        Declaration::Statement(Stmt::While { .. })
+    ));
+}
@@ -0,0 +1,88 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{
+    builtins::{number_arg, string_arg, Native},
+    interpreter::Interpreter,
+    types::{Arity, Object},
+};
+
+pub fn get_builtins() -> Vec<(&'static str, Object)> {
+    vec![
+        ("args", Native::object("args", Arity::exact(0), args)),
+        ("getenv", Native::object("getenv", Arity::exact(1), getenv)),
+        (
+            "exit",
+            Native::object(
+                "exit",
+                Arity {
+                    min: 0,
+                    max: Some(1),
+                },
+                exit,
+            ),
+        ),
+    ]
+}
+
+/// The arguments given to the script, as a new list of strings
+fn args(interpreter: &mut Interpreter, _: &[Object]) -> Object {
+    let args = &interpreter.host().args;
+    Object::new_list(
+        args.iter()
+            .map(|arg| Object::String(arg.as_str().into()))
+            .collect(),
+    )
+}
+
+/// The value of an environment variable, nil if it isn't set
+fn getenv(interpreter: &mut Interpreter, args: &[Object]) -> Object {
+    let name = string_arg("getenv", args, 0);
+    match interpreter.host().getenv(name) {
+        Some(value) => Object::String(value.into()),
+        None => Object::Null,
+    }
+}
+
+/// Unwinds out of the running script when it calls `exit()`, carrying the
+/// status. The embedder decides what ending the script means.
+pub struct Exit(pub i32);
+
+/// Runs a script, returning the status it passed to `exit()` or 0 if it
+/// ran to the end
+pub fn catch_exit(run: impl FnOnce()) -> i32 {
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(()) => 0,
+        Err(payload) => match payload.downcast::<Exit>() {
+            Ok(exit) => exit.0,
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
+
+/// Ends the script with the given status code, 0 by default
+fn exit(_: &mut Interpreter, args: &[Object]) -> Object {
+    let code = match args.first() {
+        Some(_) => number_arg("exit", args, 0),
+        None => 0.0,
+    };
+    if code.fract() != 0.0 || !(i32::MIN as f64..=i32::MAX as f64).contains(&code) {
+        panic!("exit() status must be an integer, got {code}");
+    }
+    // Doesn't run the panic hook, so nothing is printed
+    panic::resume_unwind(Box::new(Exit(code as i32)))
+}
+
+#[test]
+fn test_exit_returns_status() {
+    let (ast, mut interpreter) = crate::resolver::resolve_source(
+        "
+        var after = false;
+        fun stop() { exit(3); }
+        stop();
+        after = true;
+        ",
+    );
+    assert_eq!(interpreter.interpret(ast), 3);
+    let after = interpreter.get_global(&interpreter.interner().intern("after"));
+    assert_eq!(after, Object::Boolean(false));
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
 
 use crate::{
-    class,
-    interpreter::Interpreter,
-    syntax::{Declaration, Expr, ExprKind, Program, Stmt},
+    interpreter::{Interpreter, Local},
+    scanner::Token,
+    syntax::{ClassDecl, Declaration, Expr, ExprKind, FunctionKind, Pattern, Program, Stmt},
     types::Identifier,
 };
 
@@ -20,14 +20,29 @@ enum ClassType {
     None,
     Class,
     SubClass,
+    Trait,
+}
+
+/// A variable declared in a scope, `defined` once its initializer was resolved
+#[derive(Debug, Clone, Copy)]
+struct Variable {
+    defined: bool,
+    slot: usize,
 }
 
 #[derive(Debug)]
 pub struct Resolver {
     interpreter: Interpreter,
-    scopes: Vec<HashMap<Identifier, bool>>,
+    scopes: Vec<HashMap<Identifier, Variable>>,
     current_function: FunctionType,
+    /// Whether the current function is a generator, its returns end it
+    /// rather than hand a value back
+    in_generator: bool,
     current_class: ClassType,
+    /// Members provided by each trait declared so far, used to detect conflicts
+    trait_members: HashMap<Identifier, Vec<(Identifier, FunctionKind)>>,
+    /// Variants of each enum declared so far, used to check match exhaustiveness
+    enum_variants: HashMap<Identifier, Vec<Identifier>>,
 }
 
 impl Resolver {
@@ -36,13 +51,16 @@ impl Resolver {
             interpreter,
             scopes: Default::default(),
             current_function: FunctionType::None,
+            in_generator: false,
             current_class: ClassType::None,
+            trait_members: HashMap::default(),
+            enum_variants: HashMap::default(),
         }
     }
     pub fn run(mut self, prog: &Program) -> Interpreter {
         match prog {
             Program::Declarations(decls) => {
-                self.resolve(&decls);
+                self.resolve(decls);
             }
         }
         self.interpreter
@@ -52,12 +70,16 @@ impl Resolver {
         for stmt in stmts {
             match stmt {
                 Declaration::Var {
-                    identifier,
+                    binding,
                     expression,
                 } => {
-                    self.declare(identifier.clone());
+                    for name in binding.names() {
+                        self.declare(name.clone());
+                    }
                     self.resolve_expr(expression);
-                    self.define(identifier.clone());
+                    for name in binding.names() {
+                        self.define(name.clone());
+                    }
                 }
                 Declaration::Statement(stmt) => {
                     self.resolve_stmt(stmt);
@@ -98,12 +120,48 @@ impl Resolver {
                     panic!("can't return from an initializer")
                 }
 
+                self.resolve_expr(value);
+                if !self.in_generator && matches!(value.kind, ExprKind::Call { .. }) {
+                    self.interpreter.resolve_tail_call(value);
+                }
+            }
+            Stmt::Yield { keyword, value } => {
+                match self.current_function {
+                    FunctionType::None => {
+                        panic!(
+                            "[line {}] can't yield outside of a function",
+                            keyword.line + 1
+                        )
+                    }
+                    FunctionType::Initializer => {
+                        panic!(
+                            "[line {}] can't yield from an initializer",
+                            keyword.line + 1
+                        )
+                    }
+                    FunctionType::Function | FunctionType::Method => {}
+                }
+
                 self.resolve_expr(value);
             }
             Stmt::While { condition, body } => {
                 self.resolve_expr(condition);
                 self.resolve_stmt(body);
             }
+            Stmt::ForIn {
+                binding,
+                iterable,
+                body,
+            } => {
+                self.resolve_expr(iterable);
+                self.begin_scope();
+                for name in binding.names() {
+                    self.declare(name.clone());
+                    self.define(name.clone());
+                }
+                self.resolve_stmt(body);
+                self.end_scope();
+            }
             Stmt::Block(declarations) => {
                 self.begin_scope();
                 self.resolve(declarations);
@@ -119,7 +177,7 @@ impl Resolver {
                 if let Some(superclass) = &class_decl.superclass {
                     match &superclass.kind {
                         ExprKind::Var { name } => {
-                            if name.as_ref() == class_decl.name.as_ref() {
+                            if *name == class_decl.name {
                                 panic!("a class can't inherit from itself");
                             }
                         }
@@ -129,22 +187,22 @@ impl Resolver {
                     self.resolve_expr(superclass);
                 }
 
+                self.resolve_traits(class_decl);
+
                 if class_decl.superclass.is_some() {
                     self.begin_scope();
-                    self.scopes
-                        .last_mut()
-                        .unwrap()
-                        .insert(Identifier("super".into()), true);
+                    self.declare(Identifier::SUPER);
+                    self.define(Identifier::SUPER);
                 }
 
                 self.begin_scope();
-                self.scopes
-                    .last_mut()
-                    .unwrap()
-                    .insert(Identifier("this".into()), true);
+                self.declare(Identifier::THIS);
+                self.define(Identifier::THIS);
 
                 for method in &class_decl.methods {
-                    let declaration = if method.identifier.as_ref() == "init" {
+                    let declaration = if method.kind == FunctionKind::Function
+                        && method.identifier == Identifier::INIT
+                    {
                         FunctionType::Initializer
                     } else {
                         FunctionType::Method
@@ -152,12 +210,77 @@ impl Resolver {
                     self.resolve_function(method, declaration);
                 }
 
+                // Static methods share the scope above, but 'this' is bound to the class
+                for method in &class_decl.static_methods {
+                    self.resolve_function(method, FunctionType::Method);
+                }
+
                 self.end_scope();
 
                 if class_decl.superclass.is_some() {
                     self.end_scope();
                 }
                 self.current_class = enclosing_class;
+
+                // Static fields are evaluated in the scope enclosing the class
+                for (_, initializer) in &class_decl.static_fields {
+                    self.resolve_expr(initializer);
+                }
+            }
+            Stmt::EnumDecl(enum_decl) => {
+                self.declare(enum_decl.name.clone());
+                self.define(enum_decl.name.clone());
+
+                let variants = enum_decl
+                    .variants
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                self.enum_variants.insert(enum_decl.name.clone(), variants);
+            }
+            Stmt::Match {
+                keyword,
+                value,
+                arms,
+            } => {
+                self.resolve_expr(value);
+                for arm in arms {
+                    self.resolve_pattern(keyword, &arm.pattern);
+                    self.begin_pattern_scope(&arm.pattern);
+                    self.resolve_stmt(&arm.body);
+                    self.end_scope();
+                }
+                self.check_exhaustive(keyword, arms.iter().map(|arm| &arm.pattern));
+            }
+            Stmt::TraitDecl(trait_decl) => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Trait;
+
+                self.declare(trait_decl.name.clone());
+                self.define(trait_decl.name.clone());
+
+                self.begin_scope();
+                self.declare(Identifier::THIS);
+                self.define(Identifier::THIS);
+
+                for method in &trait_decl.methods {
+                    if method.kind == FunctionKind::Function
+                        && method.identifier == Identifier::INIT
+                    {
+                        panic!("trait '{}' can't define an initializer", trait_decl.name);
+                    }
+                    self.resolve_function(method, FunctionType::Method);
+                }
+
+                self.end_scope();
+                self.current_class = enclosing_class;
+
+                let members = trait_decl
+                    .methods
+                    .iter()
+                    .map(|method| (method.identifier.clone(), method.kind))
+                    .collect();
+                self.trait_members.insert(trait_decl.name.clone(), members);
             }
         }
     }
@@ -167,7 +290,7 @@ impl Resolver {
         match expr_kind {
             ExprKind::Assign { name, expr: child } => {
                 self.resolve_expr(child);
-                self.resolve_local(expr, name);
+                self.resolve_local(expr.id, name);
             }
             ExprKind::Binary { left, op: _, right } => {
                 self.resolve_expr(left);
@@ -188,17 +311,22 @@ impl Resolver {
                 callee,
                 parens: _,
                 args,
+                named_args,
             } => {
                 self.resolve_expr(callee);
                 for arg in args {
                     self.resolve_expr(arg);
                 }
+                for (_, arg) in named_args {
+                    self.resolve_expr(arg);
+                }
             }
             ExprKind::Var { name } => {
-                if let Some(false) = self.scopes.last().map(|s| s.get(name).unwrap_or(&true)) {
+                let variable = self.scopes.last().and_then(|scope| scope.get(name));
+                if variable.is_some_and(|variable| !variable.defined) {
                     panic!("can't read local var in its own initializer");
                 }
-                self.resolve_local(expr, name);
+                self.resolve_local(expr.id, name);
             }
             ExprKind::Get { name: _, object } => {
                 self.resolve_expr(object);
@@ -216,17 +344,207 @@ impl Resolver {
                     panic!("can't use 'this' keyword outside of a class");
                 }
 
-                self.resolve_local(expr, token);
+                self.resolve_local(expr.id, token);
+            }
+            ExprKind::Match {
+                keyword,
+                value,
+                arms,
+            } => {
+                self.resolve_expr(value);
+                for arm in arms {
+                    self.resolve_pattern(keyword, &arm.pattern);
+                    self.begin_pattern_scope(&arm.pattern);
+                    self.resolve_expr(&arm.body);
+                    self.end_scope();
+                }
+                self.check_exhaustive(keyword, arms.iter().map(|arm| &arm.pattern));
+            }
+            ExprKind::List { items } => {
+                for item in items {
+                    self.resolve_expr(item);
+                }
+            }
+            ExprKind::Map { entries } => {
+                for (_, value) in entries {
+                    self.resolve_expr(value);
+                }
+            }
+            ExprKind::Spread { expr } => {
+                self.resolve_expr(expr);
+            }
+            ExprKind::Index { object, index } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            ExprKind::Destructure { target, value } => {
+                self.resolve_expr(value);
+                self.resolve_target(target);
             }
             ExprKind::Super { token, method: _ } => {
                 if self.current_class == ClassType::None {
                     panic!("can't use 'super' outside of class");
+                } else if self.current_class == ClassType::Trait {
+                    panic!("can't use 'super' in a trait, trait methods have no superclass");
                 } else if self.current_class != ClassType::SubClass {
                     panic!("can't use 'super' in a class with no superclass");
                 }
-                self.resolve_local(expr, token);
+                self.resolve_local(expr.id, token);
+            }
+        }
+    }
+
+    /// Resolves the traits mixed into a class, rejecting members that are
+    /// provided by more than one trait unless the class overrides them.
+    fn resolve_traits(&mut self, class_decl: &ClassDecl) {
+        let mut provided_by: HashMap<(Identifier, FunctionKind), Identifier> = HashMap::new();
+        for trait_expr in &class_decl.traits {
+            let ExprKind::Var { name: trait_name } = &trait_expr.kind else {
+                panic!("bug: trait is not a var??");
+            };
+            self.resolve_expr(trait_expr);
+
+            let Some(members) = self.trait_members.get(trait_name) else {
+                continue;
+            };
+            for member in members {
+                let overridden = class_decl
+                    .methods
+                    .iter()
+                    .any(|method| method.identifier == member.0 && method.kind == member.1);
+                if overridden {
+                    continue;
+                }
+                if let Some(other) = provided_by.insert(member.clone(), trait_name.clone()) {
+                    panic!(
+                        "class '{}' gets conflicting '{}' from traits '{other}' and '{trait_name}', override it in the class",
+                        class_decl.name, member.0
+                    );
+                }
+            }
+        }
+    }
+
+    /// Resolves the assignable expressions of a destructuring assignment
+    fn resolve_target(&mut self, target: &Expr) {
+        match &target.kind {
+            ExprKind::Var { name } => self.resolve_local(target.id, name),
+            ExprKind::List { items } => {
+                for item in items {
+                    self.resolve_target(item);
+                }
+            }
+            ExprKind::Spread { expr } => self.resolve_target(expr),
+            _ => self.resolve_expr(target),
+        }
+    }
+
+    /// Finds the enum a variant pattern belongs to, which must be unique for a
+    /// bare variant, and resolves it like a variable
+    fn resolve_pattern(&mut self, keyword: &Token, pattern: &Pattern) {
+        let Pattern::Variant {
+            enumeration,
+            id,
+            name,
+            ..
+        } = pattern
+        else {
+            return;
+        };
+        let enumeration = enumeration.get_or_init(|| {
+            let mut candidates: Vec<_> = self
+                .enum_variants
+                .iter()
+                .filter(|(_, variants)| variants.contains(name))
+                .map(|(enumeration, _)| enumeration)
+                .collect();
+            candidates.sort_by_key(|enumeration| enumeration.to_string());
+            match &candidates[..] {
+                [enumeration] => (*enumeration).clone(),
+                [] => panic!("[line {}] no enum has a variant '{name}'", keyword.line + 1),
+                _ => panic!(
+                    "[line {}] variant '{name}' is ambiguous, write one of {}",
+                    keyword.line + 1,
+                    (candidates.iter())
+                        .map(|enumeration| format!("{enumeration}.{name}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        });
+        self.resolve_local(*id, enumeration);
+    }
+
+    /// Begins the scope of a match arm, binding the variables of its pattern
+    fn begin_pattern_scope(&mut self, pattern: &Pattern) {
+        self.begin_scope();
+        if let Pattern::Variant {
+            bindings: Some(bindings),
+            ..
+        } = pattern
+        {
+            for name in bindings
+                .iter()
+                .filter(|name| **name != Identifier::WILDCARD)
+            {
+                self.declare(name.clone());
+                self.define(name.clone());
+            }
+        }
+    }
+
+    /// Warns when the arms of a match don't cover every variant of the enum
+    /// being matched. Matches with a `_` arm are always exhaustive.
+    fn check_exhaustive<'a>(&self, keyword: &Token, patterns: impl Iterator<Item = &'a Pattern>) {
+        let mut enumeration = None;
+        let mut covered = Vec::new();
+        for pattern in patterns {
+            match pattern {
+                Pattern::Wildcard => return,
+                Pattern::Literal(_) => {}
+                Pattern::Variant {
+                    enumeration: resolved,
+                    name,
+                    ..
+                } => {
+                    covered.push(name);
+                    if enumeration.is_none() {
+                        enumeration = resolved.get();
+                    }
+                }
             }
         }
+
+        let Some(variants) = enumeration.and_then(|e| self.enum_variants.get(e)) else {
+            eprintln!(
+                "[line {}] Warning: match has no '_' arm and may not be exhaustive",
+                keyword.line + 1
+            );
+            return;
+        };
+
+        let missing: Vec<_> = variants
+            .iter()
+            .filter(|variant| !covered.contains(variant))
+            .map(|variant| variant.to_string())
+            .collect();
+        if !missing.is_empty() {
+            eprintln!(
+                "[line {}] Warning: non-exhaustive match on {}, missing {}",
+                keyword.line + 1,
+                enumeration.unwrap(),
+                missing.join(", ")
+            );
+        }
     }
 
     fn begin_scope(&mut self) {
@@ -237,26 +555,42 @@ impl Resolver {
         self.scopes.pop().expect("stack is empty!");
     }
 
+    /// Declares a variable in the innermost scope, giving it the next slot.
+    /// The interpreter defines variables in the same order.
     fn declare(&mut self, name: Identifier) {
         if let Some(scope) = self.scopes.last_mut() {
             if scope.contains_key(&name) {
                 panic!("Already a variable with this name in this scope. {scope:?}");
             }
-            scope.insert(name, false);
+            let slot = scope.len();
+            scope.insert(
+                name,
+                Variable {
+                    defined: false,
+                    slot,
+                },
+            );
         }
     }
 
     fn define(&mut self, name: Identifier) {
-        self.scopes
+        if let Some(variable) = self
+            .scopes
             .last_mut()
-            .and_then(|scope| scope.insert(name, true));
+            .and_then(|scope| scope.get_mut(&name))
+        {
+            variable.defined = true;
+        }
     }
 
-    fn resolve_local(&mut self, expr: &Expr, name: &Identifier) {
+    fn resolve_local(&mut self, id: u64, name: &Identifier) {
         for (i, scope) in self.scopes.iter().enumerate().rev() {
-            if scope.contains_key(name) {
-                let depth = self.scopes.len() - 1 - i;
-                self.interpreter.resolve(expr, depth);
+            if let Some(variable) = scope.get(name) {
+                let local = Local {
+                    depth: self.scopes.len() - 1 - i,
+                    slot: variable.slot,
+                };
+                self.interpreter.resolve(id, local);
                 return;
             }
         }
@@ -269,15 +603,65 @@ impl Resolver {
     ) {
         let enclosing_function = self.current_function;
         self.current_function = kind;
+        let enclosing_generator = self.in_generator;
+        self.in_generator = function_stmt.is_generator;
 
         self.begin_scope();
         for param in &function_stmt.parameters {
-            let ident = Identifier(param.lexeme.clone());
-            self.declare(ident.clone());
-            self.define(ident);
+            self.declare(param.name.clone());
+            // Defaults are evaluated in the function's scope, after the previous parameters
+            if let Some(default) = &param.default {
+                self.resolve_expr(default);
+            }
+            self.define(param.name.clone());
+        }
+        if let Some(rest) = &function_stmt.rest {
+            self.declare(rest.clone());
+            self.define(rest.clone());
         }
         self.resolve(&function_stmt.body);
         self.end_scope();
         self.current_function = enclosing_function;
+        self.in_generator = enclosing_generator;
     }
 }
+
+/// Parses and resolves a script, returning the interpreter ready to run it
+#[cfg(test)]
+pub(crate) fn resolve_source(source: &str) -> (Program, Interpreter) {
+    let interpreter = Interpreter::default();
+    let ast = crate::syntax::parse_source(source, interpreter.interner());
+    let interpreter = Resolver::new(interpreter).run(&ast);
+    (ast, interpreter)
+}
+
+#[test]
+#[should_panic(expected = "conflicting 'greet' from traits 'Loud' and 'Quiet'")]
+fn test_trait_conflict() {
+    let source = "
+        trait Loud { greet() { return 1; } }
+        trait Quiet { greet() { return 2; } }
+        class Speaker with Loud, Quiet {}
+    ";
+    resolve_source(source);
+}
+
+#[test]
+#[should_panic(expected = "can't yield from an initializer")]
+fn test_yield_in_initializer() {
+    let source = "
+        class Counter { init() { yield 1; } }
+    ";
+    resolve_source(source);
+}
+
+#[test]
+#[should_panic(expected = "[line 4] variant 'Off' is ambiguous, write one of Light.Off, Power.Off")]
+fn test_ambiguous_variant() {
+    let source = "
+        enum Power { On, Off }
+        enum Light { Off, Blinking }
+        match (Light.Off) { Off => print 1; _ => print 2; }
+    ";
+    resolve_source(source);
+}
@@ -27,7 +27,7 @@ impl<'a> Scanner<'a> {
         Self { contents: c }
     }
 
-    pub fn scan_tokens(&self) -> Tokens {
+    pub fn scan_tokens(&self) -> Tokens<'_> {
         Tokens {
             contents: self.contents,
             chars: self.contents.chars().peekable(),
@@ -108,8 +108,19 @@ impl Iterator for Tokens<'_> {
                 ')' => Some(TokenType::RightParen),
                 '{' => Some(TokenType::LeftBrace),
                 '}' => Some(TokenType::RightBrace),
+                '[' => Some(TokenType::LeftBracket),
+                ']' => Some(TokenType::RightBracket),
                 ',' => Some(TokenType::Comma),
-                '.' => Some(TokenType::Dot),
+                '.' => {
+                    if self.contents[self.cursor..].starts_with("..") {
+                        self.advance();
+                        self.advance();
+                        Some(TokenType::Ellipsis)
+                    } else {
+                        Some(TokenType::Dot)
+                    }
+                }
+                ':' => Some(TokenType::Colon),
                 '-' => Some(TokenType::Minus),
                 '+' => Some(TokenType::Plus),
                 ';' => Some(TokenType::Semicolon),
@@ -126,6 +137,8 @@ impl Iterator for Tokens<'_> {
                 '=' => {
                     if self.matches('=') {
                         Some(TokenType::EqualEqual)
+                    } else if self.matches('>') {
+                        Some(TokenType::FatArrow)
                     } else {
                         Some(TokenType::Equal)
                     }
@@ -163,7 +176,7 @@ impl Iterator for Tokens<'_> {
                     }
                     if self.advance().is_none() {
                         return Some(Err(ParserError::UnterminatedString {
-                            line: self.line,
+                            line: self.line + 1,
                             loc: self.contents[literal_start..self.cursor].to_string(),
                             msg: "Unterminated string".to_string(),
                         }));
@@ -198,8 +211,8 @@ impl Iterator for Tokens<'_> {
                             }
                         }
                         Some(TokenType::Number)
-                    } else if c.is_alphabetic() {
-                        while self.next_if(|c| c.is_alphanumeric()).is_some() {}
+                    } else if c.is_alphabetic() || c == '_' {
+                        while self.next_if(|c| c.is_alphanumeric() || *c == '_').is_some() {}
 
                         if let Some(reserved) =
                             try_reserved(&self.contents[literal_start..self.cursor])
@@ -210,7 +223,7 @@ impl Iterator for Tokens<'_> {
                         }
                     } else {
                         return Some(Err(ParserError::UnexpectedToken {
-                            line: self.line,
+                            line: self.line + 1,
                             loc: self.contents[literal_start..self.cursor].to_string(),
                             msg: "Unexpected token".to_string(),
                         }));
@@ -242,19 +255,26 @@ pub fn try_reserved(word: &str) -> Option<TokenType> {
         "and" => Some(TokenType::And),
         "class" => Some(TokenType::Class),
         "else" => Some(TokenType::Else),
+        "enum" => Some(TokenType::Enum),
         "false" => Some(TokenType::False),
         "for" => Some(TokenType::For),
         "fun" => Some(TokenType::Fun),
         "if" => Some(TokenType::If),
+        "in" => Some(TokenType::In),
+        "match" => Some(TokenType::Match),
         "nil" => Some(TokenType::Nil),
         "or" => Some(TokenType::Or),
         "print" => Some(TokenType::Print),
         "return" => Some(TokenType::Return),
+        "static" => Some(TokenType::Static),
         "super" => Some(TokenType::Super),
         "this" => Some(TokenType::This),
+        "trait" => Some(TokenType::Trait),
         "true" => Some(TokenType::True),
         "var" => Some(TokenType::Var),
         "while" => Some(TokenType::While),
+        "with" => Some(TokenType::With),
+        "yield" => Some(TokenType::Yield),
         _ => None,
     }
 }
@@ -266,8 +286,11 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    Colon,
     Minus,
     Plus,
     Semicolon,
@@ -283,6 +306,8 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    FatArrow,
+    Ellipsis,
     Comment,
 
     // Literals.
@@ -294,19 +319,26 @@ pub enum TokenType {
     And,
     Class,
     Else,
+    Enum,
     False,
     Fun,
     For,
     If,
+    In,
+    Match,
     Nil,
     Or,
     Print,
     Return,
+    Static,
     Super,
     This,
+    Trait,
     True,
     Var,
     While,
+    With,
+    Yield,
 
     #[allow(dead_code)]
     Eof,
@@ -0,0 +1,135 @@
+use std::rc::Rc;
+
+use crate::{
+    builtins::string_arg,
+    class::ClassInstance,
+    interpreter::Interpreter,
+    types::{Arity, Callable, Identifier, Object},
+};
+
+type Method = fn(&str, &[Object]) -> Object;
+
+/// The methods available on every string. Positions and lengths count
+/// characters, not bytes.
+const METHODS: &[(&str, Arity, Method)] = &[
+    ("len", Arity::exact(0), len),
+    (
+        "substring",
+        Arity {
+            min: 1,
+            max: Some(2),
+        },
+        substring,
+    ),
+    ("indexOf", Arity::exact(1), index_of),
+    ("split", Arity::exact(1), split),
+    ("replace", Arity::exact(2), replace),
+    ("upper", Arity::exact(0), |s, _| string(s.to_uppercase())),
+    ("lower", Arity::exact(0), |s, _| string(s.to_lowercase())),
+    ("trim", Arity::exact(0), |s, _| string(s.trim())),
+    ("startsWith", Arity::exact(1), starts_with),
+    ("chars", Arity::exact(0), chars),
+];
+
+/// A string method bound to the string it was accessed on
+struct StringMethod {
+    receiver: Rc<str>,
+    name: &'static str,
+    arity: Arity,
+    method: Method,
+}
+
+pub fn get_method(receiver: &Rc<str>, name: &Identifier) -> Object {
+    let Some(&(name, arity, method)) = METHODS.iter().find(|(n, _, _)| *n == name.name()) else {
+        panic!("strings have no method '{name}'");
+    };
+    Object::Callable(Rc::new(StringMethod {
+        receiver: receiver.clone(),
+        name,
+        arity,
+        method,
+    }))
+}
+
+impl Callable for StringMethod {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, _: &mut Interpreter, args: &[Object]) -> Object {
+        (self.method)(&self.receiver, args)
+    }
+
+    fn bind(&self, _instance: &Rc<ClassInstance>) -> Object {
+        unimplemented!("can't bind string method {}", self.name)
+    }
+}
+
+fn string(s: impl Into<Rc<str>>) -> Object {
+    Object::String(s.into())
+}
+
+/// A character position, which can be at most `len`
+fn position_arg(method: &str, args: &[Object], i: usize, len: usize) -> usize {
+    match args[i] {
+        Object::Number(n) if n.fract() == 0.0 && n >= 0.0 && n <= len as f64 => n as usize,
+        Object::Number(n) => panic!("{method}() position {n} out of range for length {len}"),
+        ref other => panic!("{method}() expects a number argument, got '{other:?}'"),
+    }
+}
+
+fn len(s: &str, _: &[Object]) -> Object {
+    Object::Number(s.chars().count() as f64)
+}
+
+/// `substring(start, end)`, `end` defaults to the end of the string
+fn substring(s: &str, args: &[Object]) -> Object {
+    let len = s.chars().count();
+    let start = position_arg("substring", args, 0, len);
+    let end = match args.get(1) {
+        Some(_) => position_arg("substring", args, 1, len),
+        None => len,
+    };
+    if start > end {
+        panic!("substring() start {start} is after end {end}");
+    }
+    string(s.chars().skip(start).take(end - start).collect::<String>())
+}
+
+/// The position of the first occurrence of the argument, -1 if there's none
+fn index_of(s: &str, args: &[Object]) -> Object {
+    let needle: &str = string_arg("indexOf", args, 0);
+    let index = match s.find(needle) {
+        Some(bytes) => s[..bytes].chars().count() as f64,
+        None => -1.0,
+    };
+    Object::Number(index)
+}
+
+/// Splits on every occurrence of the separator, an empty separator splits
+/// into characters
+fn split(s: &str, args: &[Object]) -> Object {
+    let separator: &str = string_arg("split", args, 0);
+    if separator.is_empty() {
+        return chars(s, args);
+    }
+    Object::new_list(s.split(separator).map(string).collect())
+}
+
+/// Replaces every occurrence of the first argument with the second
+fn replace(s: &str, args: &[Object]) -> Object {
+    let from: &str = string_arg("replace", args, 0);
+    let to: &str = string_arg("replace", args, 1);
+    if from.is_empty() {
+        panic!("replace() can't replace an empty string");
+    }
+    string(s.replace(from, to))
+}
+
+fn starts_with(s: &str, args: &[Object]) -> Object {
+    Object::Boolean(s.starts_with(string_arg("startsWith", args, 0).as_ref()))
+}
+
+fn chars(s: &str, _: &[Object]) -> Object {
+    Object::new_list(s.chars().map(|c| string(c.to_string())).collect())
+}
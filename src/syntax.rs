@@ -1,11 +1,24 @@
 use eyre::Context;
-use std::{fmt::Display, iter::Peekable};
+use std::{cell::OnceCell, fmt::Display, iter::Peekable, rc::Rc};
 
 use crate::{
-    scanner::{Token, TokenType},
+    generator::body_yields,
+    interner::Interner,
+    scanner::{Scanner, Token, TokenType},
     types::{Identifier, Object},
 };
 
+/// Scans and parses a whole script, skipping comments, with its names
+/// interned by `interner`
+pub fn parse_source(source: &str, interner: &Interner) -> Program {
+    let scanner = Scanner::new(source);
+    let tokens = scanner
+        .scan_tokens()
+        .map(|t| t.unwrap())
+        .filter(|t| t.typ != TokenType::Comment);
+    Parser::new(tokens, interner.clone()).parse()
+}
+
 /// The AST for the program is represented as an enum
 #[derive(Debug)]
 pub enum Program {
@@ -14,32 +27,124 @@ pub enum Program {
 
 #[derive(Debug, Clone)]
 pub enum Declaration {
-    Var {
-        identifier: Identifier,
-        expression: Expr,
-    },
+    Var { binding: Binding, expression: Expr },
     Statement(Stmt),
 }
 
+/// The left hand side of a variable declaration
+#[derive(Debug, Clone)]
+pub enum Binding {
+    Name(Identifier),
+    /// `[a, b, ...rest]`
+    List {
+        items: Vec<Binding>,
+        rest: Option<Identifier>,
+    },
+    /// `{x, y: name}`, reads map keys or instance properties
+    Map {
+        entries: Vec<(Identifier, Binding)>,
+    },
+}
+
+impl Binding {
+    /// Every variable name introduced by the binding
+    pub fn names(&self) -> Vec<&Identifier> {
+        match self {
+            Binding::Name(name) => vec![name],
+            Binding::List { items, rest } => items
+                .iter()
+                .flat_map(|item| item.names())
+                .chain(rest.iter())
+                .collect(),
+            Binding::Map { entries } => entries.iter().flat_map(|(_, b)| b.names()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FunctionKind {
+    Function,
+    /// A method declared without a parameter list, invoked on property access
+    Getter,
+    /// A method declared as `set name(value)`, invoked on property assignment
+    Setter,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: Identifier,
+    pub line: usize,
+    /// Evaluated at call time, in the scope of the function, when the argument is missing
+    pub default: Option<Expr>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FunctionStmt {
     pub identifier: Identifier,
-    pub parameters: Vec<Token>,
+    pub kind: FunctionKind,
+    pub parameters: Vec<Parameter>,
+    /// `...name`, collects the remaining positional arguments in a list
+    pub rest: Option<Identifier>,
     pub body: Vec<Declaration>,
+    /// Whether the body yields, calling a generator function returns a generator
+    pub is_generator: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct ClassDecl {
     pub name: Identifier,
-    pub methods: Vec<FunctionStmt>,
+    pub methods: Vec<Rc<FunctionStmt>>,
+    pub static_methods: Vec<Rc<FunctionStmt>>,
+    /// Class-level fields, initialized once the class itself is defined
+    pub static_fields: Vec<(Identifier, Expr)>,
     pub superclass: Option<Expr>,
+    /// Traits mixed into the class with `with`, in declaration order
+    pub traits: Vec<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraitDecl {
+    pub name: Identifier,
+    pub methods: Vec<Rc<FunctionStmt>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDecl {
+    pub name: Identifier,
+    /// Each variant along with the names of its payload fields
+    pub variants: Vec<(Identifier, Vec<Identifier>)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `_`, matches anything
+    Wildcard,
+    Literal(Object),
+    /// `Red`, `Color.Red` or `Blue(r, g, b)`. Bindings are always irrefutable
+    Variant {
+        /// `Color` in `Color.Red`. The resolver fills it in for a bare `Red`
+        enumeration: OnceCell<Identifier>,
+        /// Looks up the enum like a variable, so values match by identity
+        id: u64,
+        name: Identifier,
+        bindings: Option<Vec<Identifier>>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm<T> {
+    pub pattern: Pattern,
+    pub body: T,
 }
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Expr(Expr),
-    FunctionDecl(FunctionStmt),
+    /// Shared with the functions created from it, which don't copy the body
+    FunctionDecl(Rc<FunctionStmt>),
     ClassDecl(ClassDecl),
+    TraitDecl(TraitDecl),
+    EnumDecl(EnumDecl),
     If {
         condition: Expr,
         then_branch: Box<Stmt>,
@@ -49,17 +154,35 @@ pub enum Stmt {
     Return {
         value: Expr,
     },
+    /// Suspends the enclosing generator function, handing `value` to its caller
+    Yield {
+        keyword: Token,
+        value: Expr,
+    },
     While {
         condition: Expr,
         body: Box<Stmt>,
     },
+    /// `for (var x in iterable)`, the binding is fresh on every iteration
+    ForIn {
+        binding: Binding,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
     Block(Vec<Declaration>),
+    Match {
+        keyword: Token,
+        value: Expr,
+        arms: Vec<MatchArm<Stmt>>,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub struct Expr {
     /// Unique identifier for this expr in the AST
     pub id: u64,
+    /// The line of the token this expr was completed at
+    pub line: usize,
     pub kind: ExprKind,
 }
 
@@ -94,6 +217,8 @@ pub enum ExprKind {
         #[allow(dead_code)]
         parens: Token,
         args: Vec<Expr>,
+        /// Arguments passed as `name: value`, always after the positional ones
+        named_args: Vec<(Identifier, Expr)>,
     },
     Get {
         name: Identifier,
@@ -114,6 +239,35 @@ pub enum ExprKind {
     Var {
         name: Identifier,
     },
+    Match {
+        keyword: Token,
+        value: Box<Expr>,
+        arms: Vec<MatchArm<Expr>>,
+    },
+    List {
+        items: Vec<Expr>,
+    },
+    Map {
+        entries: Vec<(Rc<str>, Expr)>,
+    },
+    /// `...expr`, only valid as an item of a list literal
+    Spread {
+        expr: Box<Expr>,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    /// `[a, b] = value`, the target is a list literal of assignable expressions
+    Destructure {
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
 }
 
 impl Display for Expr {
@@ -149,12 +303,19 @@ impl Display for Expr {
                 callee,
                 parens: _,
                 args,
+                named_args,
             } => {
                 Display::fmt(&callee, f)?;
                 write!(f, "(")?;
                 for (i, arg) in args.iter().enumerate() {
                     Display::fmt(&arg, f)?;
-                    if i != args.len() - 1 {
+                    if i != args.len() - 1 || !named_args.is_empty() {
+                        write!(f, ",")?;
+                    }
+                }
+                for (i, (name, arg)) in named_args.iter().enumerate() {
+                    write!(f, "{name}:{arg}")?;
+                    if i != named_args.len() - 1 {
                         write!(f, ",")?;
                     }
                 }
@@ -172,11 +333,75 @@ impl Display for Expr {
             }
             ExprKind::This { token: _ } => write!(f, "this")?,
             ExprKind::Super { token: _, method } => write!(f, "super.{method}")?,
+            ExprKind::Match {
+                keyword: _,
+                value,
+                arms,
+            } => {
+                write!(f, "match({value}){{")?;
+                for arm in arms {
+                    write!(f, "{}=>{},", arm.pattern, arm.body)?;
+                }
+                write!(f, "}}")?;
+            }
+            ExprKind::List { items } => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    Display::fmt(item, f)?;
+                }
+                write!(f, "]")?;
+            }
+            ExprKind::Map { entries } => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{key:?}:{value}")?;
+                }
+                write!(f, "}}")?;
+            }
+            ExprKind::Spread { expr } => write!(f, "...{expr}")?,
+            ExprKind::Index { object, index } => write!(f, "{object}[{index}]")?,
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => write!(f, "{object}[{index}]={value}")?,
+            ExprKind::Destructure { target, value } => write!(f, "{target}={value}")?,
         }
         Ok(())
     }
 }
 
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal(value) => write!(f, "{value:?}"),
+            Pattern::Variant {
+                enumeration,
+                name,
+                bindings,
+                ..
+            } => {
+                if let Some(enumeration) = enumeration.get() {
+                    write!(f, "{enumeration}.")?;
+                }
+                write!(f, "{name}")?;
+                if let Some(bindings) = bindings {
+                    let bindings: Vec<_> = bindings.iter().map(|b| b.to_string()).collect();
+                    write!(f, "({})", bindings.join(","))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 macro_rules! binary_expr {
     ( $name:ident, $left:ident, $ops:expr, $right:ident ) => {
         fn $name(&mut self) -> Expr {
@@ -187,6 +412,7 @@ macro_rules! binary_expr {
                 let id = self.get_expr_id();
                 expr = Expr {
                     id,
+                    line: self.line,
                     kind: ExprKind::Binary {
                         left: Box::new(expr),
                         op,
@@ -205,17 +431,22 @@ where
     T: Iterator<Item = Token>,
 {
     tokens: Peekable<T>,
+    interner: Interner,
     expr_counter: u64,
+    /// The line of the last token consumed
+    line: usize,
 }
 
 impl<T> Parser<T>
 where
     T: Iterator<Item = Token>,
 {
-    pub fn new(tokens: T) -> Self {
+    pub fn new(tokens: T, interner: Interner) -> Self {
         Self {
             tokens: tokens.peekable(),
+            interner,
             expr_counter: 0,
+            line: 0,
         }
     }
 
@@ -235,22 +466,16 @@ where
 
     fn declaration(&mut self) -> Declaration {
         if let Some(t) = self.matches(&[TokenType::Var]) {
-            let Some(name) = self.matches(&[TokenType::Identifier]) else {
-                panic!("expected identifier on line {}", t.line);
-            };
-            // All variables must be initialized
-            self.matches(&[TokenType::Equal])
-                .unwrap_or_else(|| panic!("expected '=' after VAR on line {}", t.line));
-            let initializer = self.expression();
-            self.matches(&[TokenType::Semicolon]).expect("expected ';'");
-            Declaration::Var {
-                identifier: Identifier(name.lexeme.into()),
-                expression: initializer,
-            }
-        } else if let Some(_) = self.matches(&[TokenType::Fun]) {
+            let binding = self.binding(t.line);
+            self.var_initializer(binding, t.line)
+        } else if self.matches(&[TokenType::Fun]).is_some() {
             Declaration::Statement(self.function("function"))
-        } else if let Some(_) = self.matches(&[TokenType::Class]) {
+        } else if self.matches(&[TokenType::Class]).is_some() {
             Declaration::Statement(self.class())
+        } else if self.matches(&[TokenType::Trait]).is_some() {
+            Declaration::Statement(self.trait_declaration())
+        } else if self.matches(&[TokenType::Enum]).is_some() {
+            Declaration::Statement(self.enum_declaration())
         } else {
             let stmt = self.statement();
             Declaration::Statement(stmt)
@@ -269,22 +494,58 @@ where
 
             Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Var {
-                    name: Identifier(name.lexeme),
+                    name: self.interner.intern(&name.lexeme),
                 },
             }
         });
 
+        let mut traits = Vec::new();
+        if self.matches(&[TokenType::With]).is_some() {
+            loop {
+                let Some(name) = self.matches(&[TokenType::Identifier]) else {
+                    panic!("invalid syntax: expected trait name after 'with'")
+                };
+                traits.push(Expr {
+                    id: self.get_expr_id(),
+                    line: self.line,
+                    kind: ExprKind::Var {
+                        name: self.interner.intern(&name.lexeme),
+                    },
+                });
+                if self.matches(&[TokenType::Comma]).is_none() {
+                    break;
+                }
+            }
+        }
+
         let _ = self
             .matches(&[TokenType::LeftBrace])
             .unwrap_or_else(|| panic!("Expected '{{' after class name"));
 
         let mut methods = Vec::new();
+        let mut static_methods = Vec::new();
+        let mut static_fields = Vec::new();
         while !self.peek_matches(&[TokenType::RightBrace]) {
-            let Stmt::FunctionDecl(decl) = self.function("method") else {
-                panic!("bug: 'function' can only return function declarations")
-            };
-            methods.push(decl);
+            if self.matches(&[TokenType::Static]).is_some() {
+                let name = self
+                    .matches(&[TokenType::Identifier])
+                    .unwrap_or_else(|| panic!("Expected static member name."));
+
+                if self.matches(&[TokenType::Equal]).is_some() {
+                    let initializer = self.expression();
+                    self.matches(&[TokenType::Semicolon])
+                        .expect("expected ';' after static field");
+                    static_fields.push((self.interner.intern(&name.lexeme), initializer));
+                    continue;
+                }
+
+                static_methods.push(Rc::new(self.function_rest(name, "static method")));
+                continue;
+            }
+
+            methods.push(self.method());
         }
 
         let _ = self
@@ -292,12 +553,199 @@ where
             .unwrap_or_else(|| panic!("Expected '}}' after class body"));
 
         Stmt::ClassDecl(ClassDecl {
-            name: Identifier(name.lexeme.clone()),
+            name: self.interner.intern(&name.lexeme),
             methods,
+            static_methods,
+            static_fields,
             superclass,
+            traits,
+        })
+    }
+
+    fn trait_declaration(&mut self) -> Stmt {
+        let Some(name) = self.matches(&[TokenType::Identifier]) else {
+            panic!("invalid syntax: expected trait name")
+        };
+
+        let _ = self
+            .matches(&[TokenType::LeftBrace])
+            .unwrap_or_else(|| panic!("Expected '{{' after trait name"));
+
+        let mut methods = Vec::new();
+        while !self.peek_matches(&[TokenType::RightBrace]) {
+            methods.push(self.method());
+        }
+
+        let _ = self
+            .matches(&[TokenType::RightBrace])
+            .unwrap_or_else(|| panic!("Expected '}}' after trait body"));
+
+        Stmt::TraitDecl(TraitDecl {
+            name: self.interner.intern(&name.lexeme),
+            methods,
         })
     }
 
+    /// Parses `= expr;` after the binding of a variable declaration
+    fn var_initializer(&mut self, binding: Binding, line: usize) -> Declaration {
+        // All variables must be initialized
+        self.matches(&[TokenType::Equal])
+            .unwrap_or_else(|| panic!("expected '=' after VAR on line {}", line + 1));
+        let initializer = self.expression();
+        self.matches(&[TokenType::Semicolon]).expect("expected ';'");
+        Declaration::Var {
+            binding,
+            expression: initializer,
+        }
+    }
+
+    fn binding(&mut self, line: usize) -> Binding {
+        if self.matches(&[TokenType::LeftBracket]).is_some() {
+            let mut items = Vec::new();
+            let mut rest = None;
+            while !self.peek_matches(&[TokenType::RightBracket]) {
+                if self.matches(&[TokenType::Ellipsis]).is_some() {
+                    let name = self.matches(&[TokenType::Identifier]).unwrap_or_else(|| {
+                        panic!("expected identifier after '...' on line {}", line + 1)
+                    });
+                    rest = Some(self.interner.intern(&name.lexeme));
+                    break;
+                }
+                items.push(self.binding(line));
+                if self.matches(&[TokenType::Comma]).is_none() {
+                    break;
+                }
+            }
+            self.matches(&[TokenType::RightBracket])
+                .unwrap_or_else(|| panic!("expected ']' after list pattern on line {}", line + 1));
+            return Binding::List { items, rest };
+        }
+
+        if self.matches(&[TokenType::LeftBrace]).is_some() {
+            let mut entries = Vec::new();
+            while !self.peek_matches(&[TokenType::RightBrace]) {
+                let key = self.matches(&[TokenType::Identifier]).unwrap_or_else(|| {
+                    panic!("expected key name in map pattern on line {}", line + 1)
+                });
+                let key = self.interner.intern(&key.lexeme);
+                let binding = if self.matches(&[TokenType::Colon]).is_some() {
+                    self.binding(line)
+                } else {
+                    Binding::Name(key.clone())
+                };
+                entries.push((key, binding));
+                if self.matches(&[TokenType::Comma]).is_none() {
+                    break;
+                }
+            }
+            self.matches(&[TokenType::RightBrace])
+                .unwrap_or_else(|| panic!("expected '}}' after map pattern on line {}", line + 1));
+            return Binding::Map { entries };
+        }
+
+        let Some(name) = self.matches(&[TokenType::Identifier]) else {
+            panic!("expected identifier on line {}", line + 1);
+        };
+        Binding::Name(self.interner.intern(&name.lexeme))
+    }
+
+    fn enum_declaration(&mut self) -> Stmt {
+        let Some(name) = self.matches(&[TokenType::Identifier]) else {
+            panic!("invalid syntax: expected enum name")
+        };
+
+        let _ = self
+            .matches(&[TokenType::LeftBrace])
+            .unwrap_or_else(|| panic!("Expected '{{' after enum name"));
+
+        let mut variants = Vec::new();
+        while !self.peek_matches(&[TokenType::RightBrace]) {
+            let Some(variant) = self.matches(&[TokenType::Identifier]) else {
+                panic!(
+                    "invalid syntax: expected variant name in enum {}",
+                    name.lexeme
+                )
+            };
+            let fields = if self.matches(&[TokenType::LeftParen]).is_some() {
+                self.identifier_list("field")
+            } else {
+                Vec::new()
+            };
+            variants.push((self.interner.intern(&variant.lexeme), fields));
+
+            if self.matches(&[TokenType::Comma]).is_none() {
+                break;
+            }
+        }
+
+        let _ = self
+            .matches(&[TokenType::RightBrace])
+            .unwrap_or_else(|| panic!("Expected '}}' after enum variants"));
+
+        Stmt::EnumDecl(EnumDecl {
+            name: self.interner.intern(&name.lexeme),
+            variants,
+        })
+    }
+
+    /// Parses `a, b, c)`, the opening paren must already be consumed
+    fn identifier_list(&mut self, kind: &str) -> Vec<Identifier> {
+        let mut names = Vec::new();
+        if !self.peek_matches(&[TokenType::RightParen]) {
+            loop {
+                let name = self
+                    .matches(&[TokenType::Identifier])
+                    .unwrap_or_else(|| panic!("Expected {kind} name"));
+                names.push(self.interner.intern(&name.lexeme));
+                if self.matches(&[TokenType::Comma]).is_none() {
+                    break;
+                }
+            }
+        }
+        self.matches(&[TokenType::RightParen])
+            .unwrap_or_else(|| panic!("Expected ')' after {kind} names"));
+        names
+    }
+
+    fn method(&mut self) -> Rc<FunctionStmt> {
+        let name = self
+            .matches(&[TokenType::Identifier])
+            .unwrap_or_else(|| panic!("Expected method name."));
+
+        // `set` is only special when followed by the property name
+        if name.lexeme.as_ref() == "set" && self.peek_matches(&[TokenType::Identifier]) {
+            let name = self.matches(&[TokenType::Identifier]).unwrap();
+            let mut decl = self.function_rest(name, "setter");
+            if decl.parameters.len() != 1 || decl.rest.is_some() {
+                panic!(
+                    "setter '{}' must take exactly one parameter",
+                    decl.identifier
+                );
+            }
+            decl.kind = FunctionKind::Setter;
+            return Rc::new(decl);
+        }
+
+        if self.matches(&[TokenType::LeftBrace]).is_some() {
+            if name.lexeme.as_ref() == "init" {
+                panic!("an initializer can't be a getter");
+            }
+            let Stmt::Block(body) = self.block() else {
+                panic!("block should only return Stmt::Block")
+            };
+            return Rc::new(FunctionStmt {
+                identifier: self.interner.intern(&name.lexeme),
+                kind: FunctionKind::Getter,
+                parameters: Vec::new(),
+                rest: None,
+                is_generator: body_yields(&body),
+                body,
+            });
+        }
+
+        Rc::new(self.function_rest(name, "method"))
+    }
+
     fn expression(&mut self) -> Expr {
         self.assignment()
     }
@@ -312,6 +760,7 @@ where
                 ExprKind::Var { name } => {
                     return Expr {
                         id: self.get_expr_id(),
+                        line: self.line,
                         kind: ExprKind::Assign {
                             name,
                             expr: Box::new(value),
@@ -321,6 +770,7 @@ where
                 ExprKind::Get { object, name } => {
                     return Expr {
                         id: self.get_expr_id(),
+                        line: self.line,
                         kind: ExprKind::Set {
                             object,
                             name,
@@ -328,6 +778,33 @@ where
                         },
                     }
                 }
+                ExprKind::Index { object, index } => {
+                    return Expr {
+                        id: self.get_expr_id(),
+                        line: self.line,
+                        kind: ExprKind::SetIndex {
+                            object,
+                            index,
+                            value: Box::new(value),
+                        },
+                    }
+                }
+                kind @ ExprKind::List { .. } => {
+                    let target = Expr {
+                        id: expr.id,
+                        line: expr.line,
+                        kind,
+                    };
+                    check_destructure_target(&target);
+                    return Expr {
+                        id: self.get_expr_id(),
+                        line: self.line,
+                        kind: ExprKind::Destructure {
+                            target: Box::new(target),
+                            value: Box::new(value),
+                        },
+                    };
+                }
                 _ => panic!("Invalid assignment target"),
             }
         }
@@ -343,6 +820,7 @@ where
             let right = self.and();
             expr = Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Logical {
                     left: Box::new(expr),
                     op,
@@ -362,6 +840,7 @@ where
             let right = self.equality();
             expr = Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Logical {
                     left: Box::new(expr),
                     op,
@@ -386,6 +865,10 @@ where
             return self.return_statement();
         }
 
+        if let Some(keyword) = self.matches(&[TokenType::Yield]) {
+            return self.yield_statement(keyword);
+        }
+
         if self.matches(&[TokenType::For]).is_some() {
             return self.for_statement();
         }
@@ -398,6 +881,16 @@ where
             return self.block();
         }
 
+        if let Some(keyword) = self.matches(&[TokenType::Match]) {
+            let value = self.match_value();
+            let arms = self.match_arms(|parser| parser.statement(), false);
+            return Stmt::Match {
+                keyword,
+                value,
+                arms,
+            };
+        }
+
         let expr = self.expression();
         self.matches(&[TokenType::Semicolon]).expect("expected ';'");
         Stmt::Expr(expr)
@@ -406,6 +899,7 @@ where
     fn return_statement(&mut self) -> Stmt {
         let mut value = Expr {
             id: self.get_expr_id(),
+            line: self.line,
             kind: ExprKind::Literal {
                 value: Object::Null,
             },
@@ -418,16 +912,40 @@ where
         Stmt::Return { value }
     }
 
+    fn yield_statement(&mut self, keyword: Token) -> Stmt {
+        let value = if self.peek_matches(&[TokenType::Semicolon]) {
+            Expr {
+                id: self.get_expr_id(),
+                line: self.line,
+                kind: ExprKind::Literal {
+                    value: Object::Null,
+                },
+            }
+        } else {
+            self.expression()
+        };
+
+        self.matches(&[TokenType::Semicolon]).expect("expected ';'");
+        Stmt::Yield { keyword, value }
+    }
+
     fn for_statement(&mut self) -> Stmt {
         self.matches(&[TokenType::LeftParen])
             .expect("expected '(' after 'for'");
 
         let initializer = if self.matches(&[TokenType::Semicolon]).is_some() {
             None
-        } else if self.peek_matches(&[TokenType::Var]) {
-            Some(self.declaration())
+        } else if let Some(t) = self.matches(&[TokenType::Var]) {
+            let binding = self.binding(t.line);
+            if self.matches(&[TokenType::In]).is_some() {
+                return self.for_in_statement(binding);
+            }
+            Some(self.var_initializer(binding, t.line))
         } else {
-            Some(Declaration::Statement(Stmt::Expr(self.expression())))
+            let expr = self.expression();
+            self.matches(&[TokenType::Semicolon])
+                .expect("expected ';' after loop initializer");
+            Some(Declaration::Statement(Stmt::Expr(expr)))
         };
 
         let condition = if let Some(token) = self.tokens.peek() {
@@ -468,6 +986,7 @@ where
 
         let condition = condition.unwrap_or(Expr {
             id: self.get_expr_id(),
+            line: self.line,
             kind: ExprKind::Literal {
                 value: Object::Boolean(true),
             },
@@ -485,6 +1004,20 @@ where
         body
     }
 
+    /// Parses the rest of `for (var x in iterable) body`
+    fn for_in_statement(&mut self, binding: Binding) -> Stmt {
+        let iterable = self.expression();
+        self.matches(&[TokenType::RightParen])
+            .expect("expected ')' after for-in iterable");
+        let body = self.statement();
+
+        Stmt::ForIn {
+            binding,
+            iterable,
+            body: Box::new(body),
+        }
+    }
+
     fn while_statement(&mut self) -> Stmt {
         self.matches(&[TokenType::LeftParen])
             .expect("expected '(' after 'while'");
@@ -501,10 +1034,7 @@ where
 
     fn block(&mut self) -> Stmt {
         let mut statements = Vec::new();
-        loop {
-            let Some(next) = self.tokens.peek() else {
-                break;
-            };
+        while let Some(next) = self.tokens.peek() {
             if next.typ == TokenType::RightBrace {
                 break;
             };
@@ -541,8 +1071,103 @@ where
         Stmt::Print(expr)
     }
 
+    fn match_value(&mut self) -> Expr {
+        self.matches(&[TokenType::LeftParen])
+            .expect("expected '(' after 'match'");
+        let value = self.expression();
+        self.matches(&[TokenType::RightParen])
+            .expect("expected ')' after match value");
+        value
+    }
+
+    /// Parses `{ pattern => body ... }`. Expression arms are separated by commas
+    fn match_arms<B>(
+        &mut self,
+        body: fn(&mut Self) -> B,
+        comma_separated: bool,
+    ) -> Vec<MatchArm<B>> {
+        self.matches(&[TokenType::LeftBrace])
+            .expect("expected '{' before match arms");
+
+        let mut arms = Vec::new();
+        while !self.peek_matches(&[TokenType::RightBrace]) {
+            let pattern = self.pattern();
+            self.matches(&[TokenType::FatArrow])
+                .unwrap_or_else(|| panic!("expected '=>' after pattern {pattern}"));
+            arms.push(MatchArm {
+                pattern,
+                body: body(self),
+            });
+
+            if comma_separated && self.matches(&[TokenType::Comma]).is_none() {
+                break;
+            }
+        }
+
+        self.matches(&[TokenType::RightBrace])
+            .expect("expected '}' after match arms");
+        arms
+    }
+
+    fn pattern(&mut self) -> Pattern {
+        let negative = self.matches(&[TokenType::Minus]).is_some();
+        if let Some(token) = self.matches(&[TokenType::Number]) {
+            let n: f64 = token
+                .lexeme
+                .parse()
+                .with_context(|| format!("parsing number {token:?}"))
+                .unwrap();
+            return Pattern::Literal(Object::Number(if negative { -n } else { n }));
+        } else if negative {
+            panic!("expected number after '-' in pattern");
+        }
+
+        if let Some(token) = self.matches(&[
+            TokenType::String,
+            TokenType::True,
+            TokenType::False,
+            TokenType::Nil,
+        ]) {
+            return Pattern::Literal(match token.typ {
+                TokenType::String => Object::String(self.interner.intern_str(&token.lexeme)),
+                TokenType::True => Object::Boolean(true),
+                TokenType::False => Object::Boolean(false),
+                _ => Object::Null,
+            });
+        }
+
+        let Some(name) = self.matches(&[TokenType::Identifier]) else {
+            panic!("expected pattern, found {:?}", self.tokens.peek());
+        };
+        if name.lexeme.as_ref() == "_" {
+            return Pattern::Wildcard;
+        }
+
+        let (enumeration, name) = if self.matches(&[TokenType::Dot]).is_some() {
+            let variant = self
+                .matches(&[TokenType::Identifier])
+                .expect("expected variant name after '.'");
+            (Some(self.interner.intern(&name.lexeme)), variant)
+        } else {
+            (None, name)
+        };
+
+        let bindings = self
+            .matches(&[TokenType::LeftParen])
+            .map(|_| self.identifier_list("binding"));
+
+        Pattern::Variant {
+            enumeration: enumeration.map(OnceCell::from).unwrap_or_default(),
+            id: self.get_expr_id(),
+            name: self.interner.intern(&name.lexeme),
+            bindings,
+        }
+    }
+
     fn matches(&mut self, types: &[TokenType]) -> Option<Token> {
-        self.tokens.next_if(|t| types.contains(&t.typ))
+        let token = self.tokens.next_if(|t| types.contains(&t.typ))?;
+        self.line = token.line;
+        Some(token)
     }
 
     fn peek_matches(&mut self, types: &[TokenType]) -> bool {
@@ -576,6 +1201,7 @@ where
         if let Some(op) = self.matches(&[TokenType::Bang, TokenType::Minus]) {
             return Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Unary {
                     op,
                     right: Box::new(self.unary()),
@@ -592,14 +1218,28 @@ where
         loop {
             if self.matches(&[TokenType::LeftParen]).is_some() {
                 expr = self.finish_call(expr);
+            } else if self.matches(&[TokenType::LeftBracket]).is_some() {
+                let index = self.expression();
+                self.matches(&[TokenType::RightBracket])
+                    .unwrap_or_else(|| panic!("expected ']' after index '{index}'"));
+                expr = Expr {
+                    id: self.get_expr_id(),
+                    line: self.line,
+                    kind: ExprKind::Index {
+                        object: Box::new(expr),
+                        index: Box::new(index),
+                    },
+                }
             } else if self.matches(&[TokenType::Dot]).is_some() {
-                let Some(name) = self.matches(&[TokenType::Identifier]) else {
+                // `instance.class` is allowed even though `class` is a keyword
+                let Some(name) = self.matches(&[TokenType::Identifier, TokenType::Class]) else {
                     panic!("Expect property name after '.'")
                 };
                 expr = Expr {
                     id: self.get_expr_id(),
+                    line: self.line,
                     kind: ExprKind::Get {
-                        name: Identifier(name.lexeme.clone()),
+                        name: self.interner.intern(&name.lexeme),
                         object: Box::new(expr),
                     },
                 }
@@ -613,15 +1253,29 @@ where
 
     fn finish_call(&mut self, callee: Expr) -> Expr {
         let mut args = Vec::new();
+        let mut named_args = Vec::new();
 
         if !self.peek_matches(&[TokenType::RightParen]) {
-            args.push(self.expression());
-            while self.matches(&[TokenType::Comma]).is_some() {
-                args.push(self.expression())
+            loop {
+                let arg = self.expression();
+                if self.matches(&[TokenType::Colon]).is_some() {
+                    let ExprKind::Var { name } = arg.kind else {
+                        panic!("expected parameter name before ':' in call, found '{arg}'");
+                    };
+                    named_args.push((name, self.expression()));
+                } else if !named_args.is_empty() {
+                    panic!("positional argument '{arg}' can't follow named arguments");
+                } else {
+                    args.push(arg);
+                }
+
+                if self.matches(&[TokenType::Comma]).is_none() {
+                    break;
+                }
             }
         }
 
-        if args.len() > 255 {
+        if args.len() + named_args.len() > 255 {
             panic!("can't have more than 255 arguments!")
         }
 
@@ -631,37 +1285,44 @@ where
 
         Expr {
             id: self.get_expr_id(),
+            line: self.line,
             kind: ExprKind::Call {
                 callee: Box::new(callee),
                 parens: tok,
                 args,
+                named_args,
             },
         }
     }
 
     fn primary(&mut self) -> Expr {
         let token = self.tokens.next().expect("unexpected end of token stream");
+        self.line = token.line;
         match token.typ {
             TokenType::False => Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Literal {
                     value: Object::Boolean(false),
                 },
             },
             TokenType::True => Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Literal {
                     value: Object::Boolean(true),
                 },
             },
             TokenType::Nil => Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Literal {
                     value: Object::Null,
                 },
             },
             TokenType::Number => Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Literal {
                     value: Object::Number(
                         token
@@ -674,8 +1335,9 @@ where
             },
             TokenType::String => Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Literal {
-                    value: Object::String(token.lexeme),
+                    value: Object::String(self.interner.intern_str(&token.lexeme)),
                 },
             },
 
@@ -691,6 +1353,7 @@ where
 
                 Expr {
                     id: self.get_expr_id(),
+                    line: self.line,
                     kind: ExprKind::Grouping {
                         expr: Box::new(expr),
                     },
@@ -698,14 +1361,16 @@ where
             }
             TokenType::Identifier => Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::Var {
-                    name: Identifier(token.lexeme),
+                    name: self.interner.intern(&token.lexeme),
                 },
             },
             TokenType::This => Expr {
                 id: self.get_expr_id(),
+                line: self.line,
                 kind: ExprKind::This {
-                    token: Identifier(token.lexeme),
+                    token: self.interner.intern(&token.lexeme),
                 },
             },
             TokenType::Super => {
@@ -719,9 +1384,72 @@ where
 
                 Expr {
                     id: self.get_expr_id(),
+                    line: self.line,
                     kind: ExprKind::Super {
-                        token: Identifier(token.lexeme),
-                        method: Identifier(method.lexeme),
+                        token: self.interner.intern(&token.lexeme),
+                        method: self.interner.intern(&method.lexeme),
+                    },
+                }
+            }
+            TokenType::LeftBracket => {
+                let mut items = Vec::new();
+                while !self.peek_matches(&[TokenType::RightBracket]) {
+                    let item = if self.matches(&[TokenType::Ellipsis]).is_some() {
+                        Expr {
+                            kind: ExprKind::Spread {
+                                expr: Box::new(self.expression()),
+                            },
+                            id: self.get_expr_id(),
+                            line: self.line,
+                        }
+                    } else {
+                        self.expression()
+                    };
+                    items.push(item);
+                    if self.matches(&[TokenType::Comma]).is_none() {
+                        break;
+                    }
+                }
+                self.matches(&[TokenType::RightBracket])
+                    .expect("expected ']' after list items");
+                Expr {
+                    id: self.get_expr_id(),
+                    line: self.line,
+                    kind: ExprKind::List { items },
+                }
+            }
+            TokenType::LeftBrace => {
+                let mut entries = Vec::new();
+                while !self.peek_matches(&[TokenType::RightBrace]) {
+                    // Keys are strings, bare identifiers are shorthand for them
+                    let key = self
+                        .matches(&[TokenType::Identifier, TokenType::String])
+                        .expect("expected identifier or string as map key");
+                    self.matches(&[TokenType::Colon])
+                        .unwrap_or_else(|| panic!("expected ':' after map key {key}"));
+                    entries.push((self.interner.intern_str(&key.lexeme), self.expression()));
+                    if self.matches(&[TokenType::Comma]).is_none() {
+                        break;
+                    }
+                }
+                self.matches(&[TokenType::RightBrace])
+                    .expect("expected '}' after map entries");
+                Expr {
+                    id: self.get_expr_id(),
+                    line: self.line,
+                    kind: ExprKind::Map { entries },
+                }
+            }
+            TokenType::Match => {
+                let value = Box::new(self.match_value());
+                let arms = self.match_arms(|parser| parser.expression(), true);
+                Expr {
+                    id: self.get_expr_id(),
+                    line: self.line,
+                    kind: ExprKind::Match {
+                        keyword: token,
+                        value,
+                        arms,
                     },
                 }
             }
@@ -733,21 +1461,49 @@ where
         let name = self
             .matches(&[TokenType::Identifier])
             .unwrap_or_else(|| panic!("Expected {kind} name."));
+        Stmt::FunctionDecl(Rc::new(self.function_rest(name, kind)))
+    }
+
+    /// Parses the parameters and body of a function whose name was already consumed
+    fn function_rest(&mut self, name: Token, kind: &str) -> FunctionStmt {
         let _ = self
             .matches(&[TokenType::LeftParen])
             .unwrap_or_else(|| panic!("Expected '(' after {kind} name"));
 
-        let mut parameters = Vec::new();
+        let mut parameters: Vec<Parameter> = Vec::new();
+        let mut rest = None;
         if !self.peek_matches(&[TokenType::RightParen]) {
             loop {
                 if parameters.len() > 255 {
                     panic!("can't define function with more than 255 params");
                 }
 
-                parameters.push(
-                    self.matches(&[TokenType::Identifier])
-                        .expect("Expected parameter name"),
-                );
+                if self.matches(&[TokenType::Ellipsis]).is_some() {
+                    let name = self
+                        .matches(&[TokenType::Identifier])
+                        .expect("Expected rest parameter name after '...'");
+                    rest = Some(self.interner.intern(&name.lexeme));
+                    if !self.peek_matches(&[TokenType::RightParen]) {
+                        panic!("rest parameter must be the last parameter of {kind}");
+                    }
+                    break;
+                }
+
+                let name = self
+                    .matches(&[TokenType::Identifier])
+                    .expect("Expected parameter name");
+                let default = self.matches(&[TokenType::Equal]).map(|_| self.expression());
+                if default.is_none() && parameters.last().is_some_and(|p| p.default.is_some()) {
+                    panic!(
+                        "parameter '{name}' without default can't follow parameters with defaults"
+                    );
+                }
+                parameters.push(Parameter {
+                    name: self.interner.intern(&name.lexeme),
+                    line: name.line,
+                    default,
+                });
+
                 if self.matches(&[TokenType::Comma]).is_none() {
                     break;
                 }
@@ -765,11 +1521,35 @@ where
             panic!("block should only return Stmt::Block")
         };
 
-        Stmt::FunctionDecl(FunctionStmt {
-            identifier: Identifier(name.lexeme),
+        let is_generator = body_yields(&body);
+        FunctionStmt {
+            identifier: self.interner.intern(&name.lexeme),
+            kind: FunctionKind::Function,
             parameters,
+            rest,
             body,
-        })
+            is_generator,
+        }
+    }
+}
+
+/// Only variables, properties, indexes and nested lists of them can be
+/// assigned to, with at most one trailing `...rest`
+fn check_destructure_target(target: &Expr) {
+    match &target.kind {
+        ExprKind::Var { .. } | ExprKind::Get { .. } | ExprKind::Index { .. } => {}
+        ExprKind::List { items } => {
+            for (i, item) in items.iter().enumerate() {
+                if let ExprKind::Spread { expr } = &item.kind {
+                    if i != items.len() - 1 || !matches!(expr.kind, ExprKind::Var { .. }) {
+                        panic!("only a trailing '...name' is allowed in '{target}'");
+                    }
+                } else {
+                    check_destructure_target(item);
+                }
+            }
+        }
+        _ => panic!("Invalid assignment target '{target}'"),
     }
 }
 
@@ -779,9 +1559,11 @@ fn test() {
 
     let expr = Expr {
         id: 0,
+        line: 0,
         kind: ExprKind::Binary {
             left: Box::new(Expr {
                 id: 1,
+                line: 0,
                 kind: ExprKind::Literal {
                     value: Object::Number(1.2),
                 },
@@ -789,6 +1571,7 @@ fn test() {
             op: Token::new(TokenType::Plus, "+", 0),
             right: Box::new(Expr {
                 id: 2,
+                line: 0,
                 kind: ExprKind::Literal {
                     value: Object::Number(3.4),
                 },
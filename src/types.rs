@@ -1,8 +1,13 @@
-use std::{fmt::Display, hash::Hash, rc::Rc};
+use std::{cell::RefCell, collections::BTreeMap, fmt::Display, hash::Hash, rc::Rc};
 
 use crate::{
-    class::{Class, ClassInstance},
+    builtins::Module,
+    callable::Function,
+    class::{Class, ClassInstance, Trait},
+    enums::{Enum, EnumValue},
+    generator::Generator,
     interpreter::Interpreter,
+    vm::Closure,
 };
 
 #[derive(Clone)]
@@ -13,6 +18,16 @@ pub enum Object {
     Callable(Rc<dyn Callable>),
     Class(Rc<Class>),
     ClassInstance(Rc<ClassInstance>),
+    Trait(Rc<Trait>),
+    Enum(Rc<Enum>),
+    EnumValue(Rc<EnumValue>),
+    /// A suspended call to a generator function
+    Generator(Rc<Generator>),
+    /// A namespace of builtins, like `math`
+    Module(Rc<Module>),
+    List(Rc<RefCell<Vec<Object>>>),
+    /// Maps are keyed by strings and kept sorted so they print deterministically
+    Map(Rc<RefCell<BTreeMap<Rc<str>, Object>>>),
     Null, // eww
 }
 
@@ -22,10 +37,17 @@ impl std::fmt::Debug for Object {
             Object::String(s) => write!(f, "{s:?}"),
             Object::Number(n) => write!(f, "{n:?}"),
             Object::Boolean(v) => write!(f, "{v:?}"),
-            Object::Callable(c) => write!(f, "<callable:{}>", c.arity()),
+            Object::Callable(c) => fmt_callable(c.as_ref(), f),
             Object::Null => write!(f, "null"),
             Object::Class(c) => write!(f, "<class:{}>", c.name),
             Object::ClassInstance(c) => write!(f, "<instance:{}>", c.class.name),
+            Object::Trait(t) => write!(f, "<trait:{}>", t.name),
+            Object::Enum(e) => write!(f, "<enum:{}>", e.name),
+            Object::EnumValue(v) => write!(f, "{v}"),
+            Object::Generator(g) => write!(f, "<generator:{}>", g.name()),
+            Object::Module(m) => write!(f, "<module:{}>", m.name),
+            Object::List(l) => f.debug_list().entries(l.borrow().iter()).finish(),
+            Object::Map(m) => f.debug_map().entries(m.borrow().iter()).finish(),
         }
     }
 }
@@ -57,11 +79,42 @@ impl PartialEq for Object {
                 unimplemented!("can't compare functions yet");
             }
             Object::Null => matches!(self, Object::Null),
-            Object::Class(_class) => {
-                unimplemented!("can't compare classes");
+            Object::Class(right) => {
+                let left = literal_or_false!(self, Class);
+                Rc::ptr_eq(left, right)
             }
-            Object::ClassInstance(_instance) => {
-                unimplemented!("can't compare class instances yet");
+            Object::ClassInstance(right) => {
+                let left = literal_or_false!(self, ClassInstance);
+                Rc::ptr_eq(left, right)
+            }
+            Object::Trait(right) => {
+                let left = literal_or_false!(self, Trait);
+                Rc::ptr_eq(left, right)
+            }
+            Object::Enum(right) => {
+                let left = literal_or_false!(self, Enum);
+                Rc::ptr_eq(left, right)
+            }
+            // Payloads are compared by `Interpreter::is_equal`, which can call `__eq__`
+            Object::EnumValue(right) => {
+                let left = literal_or_false!(self, EnumValue);
+                Rc::ptr_eq(left, right)
+            }
+            Object::Generator(right) => {
+                let left = literal_or_false!(self, Generator);
+                Rc::ptr_eq(left, right)
+            }
+            Object::Module(right) => {
+                let left = literal_or_false!(self, Module);
+                Rc::ptr_eq(left, right)
+            }
+            Object::List(right) => {
+                let left = literal_or_false!(self, List);
+                left == right
+            }
+            Object::Map(right) => {
+                let left = literal_or_false!(self, Map);
+                left == right
             }
         }
     }
@@ -70,6 +123,10 @@ impl PartialEq for Object {
 impl Eq for Object {}
 
 impl Object {
+    pub fn new_list(items: Vec<Object>) -> Self {
+        Object::List(Rc::new(RefCell::new(items)))
+    }
+
     pub fn is_truthy(&self) -> bool {
         match self {
             Object::Boolean(value) => *value,
@@ -78,45 +135,268 @@ impl Object {
     }
 }
 
+impl Object {
+    /// Formats a value inside a collection. Nested strings are quoted so
+    /// `["a, b"]` and `["a", "b"]` print differently.
+    fn fmt_nested(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Object::String(s) => write!(f, "{s:?}"),
+            item => Display::fmt(item, f),
+        }
+    }
+}
+
 impl Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Object::String(s) => write!(f, "{s}")?,
             Object::Number(n) => write!(f, "{n}")?,
             Object::Boolean(v) => write!(f, "{v}")?,
-            Object::Callable(c) => write!(f, "<callable:{}>", c.arity())?,
+            Object::Callable(c) => fmt_callable(c.as_ref(), f)?,
             Object::Null => write!(f, "null")?,
             Object::Class(c) => write!(f, "<class:{}>", c.name)?,
             Object::ClassInstance(c) => write!(f, "<instance:{}>", c.class.name)?,
+            Object::Trait(t) => write!(f, "<trait:{}>", t.name)?,
+            Object::Enum(e) => write!(f, "<enum:{}>", e.name)?,
+            Object::EnumValue(v) => write!(f, "{v}")?,
+            Object::Generator(g) => write!(f, "<generator:{}>", g.name())?,
+            Object::Module(m) => write!(f, "<module:{}>", m.name)?,
+            Object::List(l) => {
+                write!(f, "[")?;
+                for (i, item) in l.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_nested(f)?;
+                }
+                write!(f, "]")?;
+            }
+            Object::Map(m) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in m.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key:?}: ")?;
+                    value.fmt_nested(f)?;
+                }
+                write!(f, "}}")?;
+            }
         }
 
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Identifier(pub Rc<str>);
+fn fmt_callable(callable: &dyn Callable, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match callable.name() {
+        Some(name) => write!(f, "<fn {name}>"),
+        None => write!(f, "<native fn>"),
+    }
+}
+
+/// A name in the program, interned by an [`Interner`](crate::interner::Interner)
+/// so hashing and comparing it only looks at its symbol
+#[derive(Clone)]
+pub struct Identifier {
+    symbol: u32,
+    name: Name,
+}
 
-impl From<&str> for Identifier {
-    fn from(value: &str) -> Self {
-        Self(value.into())
+#[derive(Clone)]
+enum Name {
+    Preinterned(&'static str),
+    Interned(Rc<str>),
+}
+
+/// Defines the names the interpreter looks up itself as constants, they're
+/// interned before anything else so their symbols are known up front
+macro_rules! preinterned {
+    ($($constant:ident = $name:literal,)*) => {
+        pub(crate) const PREINTERNED: &[&str] = &[$($name),*];
+
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        enum Preinterned {
+            $($constant),*
+        }
+
+        impl Identifier {
+            $(pub const $constant: Identifier = Identifier {
+                symbol: Preinterned::$constant as u32,
+                name: Name::Preinterned($name),
+            };)*
+        }
+    };
+}
+
+preinterned! {
+    WILDCARD = "_",
+    THIS = "this",
+    SUPER = "super",
+    INIT = "init",
+    CLASS = "class",
+    ITERATOR = "iterator",
+    HAS_NEXT = "hasNext",
+    NEXT = "next",
+    TO_STRING = "toString",
+    STR = "__str__",
+    EQ = "__eq__",
+    NEG = "__neg__",
+    ADD = "__add__",
+    SUB = "__sub__",
+    MUL = "__mul__",
+    DIV = "__div__",
+    LT = "__lt__",
+    LE = "__le__",
+    GT = "__gt__",
+    GE = "__ge__",
+    INDEX = "__index__",
+    SET_INDEX = "__setindex__",
+    SCRIPT = "script",
+    HIDDEN = "",
+}
+
+impl Identifier {
+    pub(crate) fn new(symbol: u32, name: Rc<str>) -> Self {
+        Self {
+            symbol,
+            name: Name::Interned(name),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.name {
+            Name::Preinterned(name) => name,
+            Name::Interned(name) => name,
+        }
     }
 }
 
-impl AsRef<str> for Identifier {
-    fn as_ref(&self) -> &str {
-        &self.0
+impl PartialEq for Identifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbol == other.symbol
+    }
+}
+
+impl Eq for Identifier {}
+
+impl Hash for Identifier {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.symbol.hash(state);
+    }
+}
+
+impl std::fmt::Debug for Identifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Identifier").field(&self.name()).finish()
     }
 }
 
 impl Display for Identifier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Display::fmt(&self.0, f)
+        Display::fmt(self.name(), f)
+    }
+}
+
+/// Number of arguments a callable accepts, `max` is `None` for variadic callables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub const fn exact(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub const fn at_least(n: usize) -> Self {
+        Self { min: n, max: None }
+    }
+
+    pub fn accepts(&self, n_args: usize) -> bool {
+        n_args >= self.min && self.max.is_none_or(|max| n_args <= max)
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{max}"),
+            Some(max) => write!(f, "{}..{max}", self.min),
+            None => write!(f, "{}..", self.min),
+        }
     }
 }
 
 pub trait Callable {
-    fn arity(&self) -> usize;
+    fn arity(&self) -> Arity;
     fn call(&self, interpreter: &mut Interpreter, args: &[Object]) -> Object;
-    fn bind(&self, instance: &ClassInstance) -> Object;
+    fn bind(&self, instance: &Rc<ClassInstance>) -> Object;
+
+    /// Binds a static method, `this` refers to the class itself
+    fn bind_class(&self, _class: Rc<Class>) -> Object {
+        unimplemented!("only methods can be bound to a class")
+    }
+
+    /// The closure behind a function of the `vm` backend, which the VM
+    /// calls in a new frame rather than through `call`
+    fn as_closure(&self) -> Option<&Closure> {
+        None
+    }
+
+    /// The function behind a callable of the tree backend, which can be
+    /// called in tail position without nesting
+    fn as_function(&self) -> Option<&Function> {
+        None
+    }
+
+    /// The name declared in the source, natives don't have one
+    fn name(&self) -> Option<&Identifier> {
+        None
+    }
+
+    /// Calls with arguments passed by parameter name, only functions know
+    /// their parameter names so other callables reject them.
+    fn call_with_named(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[Object],
+        named: &[(Identifier, Object)],
+    ) -> Object {
+        if let Some((name, _)) = named.first() {
+            panic!("named argument '{name}' given to a callable without named parameters");
+        }
+        self.call(interpreter, args)
+    }
+}
+
+#[test]
+fn test_arity() {
+    let optional = Arity {
+        min: 1,
+        max: Some(3),
+    };
+    assert!(!optional.accepts(0));
+    assert!(optional.accepts(1));
+    assert!(optional.accepts(3));
+    assert!(!optional.accepts(4));
+    assert_eq!(optional.to_string(), "1..3");
+
+    let variadic = Arity::at_least(2);
+    assert!(variadic.accepts(200));
+    assert_eq!(variadic.to_string(), "2..");
+    assert_eq!(Arity::exact(2).to_string(), "2");
+}
+
+#[test]
+fn test_preinterned() {
+    let interner = crate::interner::Interner::default();
+    for (i, name) in PREINTERNED.iter().enumerate() {
+        assert_eq!(interner.intern(name).symbol, i as u32);
+    }
+    assert_eq!(interner.intern("init"), Identifier::INIT);
 }
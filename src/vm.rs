@@ -0,0 +1,1043 @@
+//! The stack virtual machine of the `vm` backend, it runs the bytecode made
+//! by the compiler.
+//!
+//! The VM shares the runtime of the tree-walking interpreter: values,
+//! classes, globals, builtins and the semantics of operators are the same,
+//! and instructions call the interpreter's helpers for anything that isn't a
+//! fast path. Its state lives on the interpreter, so natives and methods like
+//! `toString()` that call back into Lox run nested on the same stack.
+
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
+use crate::{
+    callable::FunctionRef,
+    chunk::{Chunk, Constant, FunctionProto, OpCode},
+    class::{Class, ClassInstance, Mixins, Trait},
+    disasm::disassemble_instruction,
+    enums::{Enum, Variant},
+    generator::{Coroutine, Generator},
+    interpreter::{destructure_list, Interpreter},
+    iter::Iter,
+    scanner::TokenType,
+    syntax::FunctionKind,
+    types::{Arity, Callable, Identifier, Object},
+};
+
+/// How deep calls can nest before the VM reports a stack overflow
+const FRAMES_MAX: usize = 64 * 1024;
+
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Object>,
+    frames: Vec<CallFrame>,
+    /// Upvalues still pointing into the stack
+    open_upvalues: Vec<Rc<Upvalue>>,
+    /// The iterators of the for-in loops in progress
+    iterators: Vec<Iter>,
+    /// Left by `yield` for the coroutine of the generator that yielded
+    suspended: Option<Suspended>,
+    /// Print the stack and each instruction before running it
+    pub(crate) trace: bool,
+}
+
+impl Debug for Vm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vm")
+            .field("stack", &self.stack)
+            .field("frames", &self.frames.len())
+            .finish()
+    }
+}
+
+struct CallFrame {
+    proto: Rc<FunctionProto>,
+    upvalues: Rc<[Rc<Upvalue>]>,
+    ip: usize,
+    /// Stack index of slot 0
+    base: usize,
+    /// Index of the frame's first iterator
+    iter_base: usize,
+    /// A bit for each parameter the caller gave an argument for
+    given: u64,
+    /// Whether the frame runs the initializer of a new instance, which is
+    /// what the call returns
+    constructs: bool,
+}
+
+/// A variable captured by a closure. It points into the stack while the
+/// variable's scope is running and holds the value once it ends.
+pub struct Upvalue(RefCell<UpvalueState>);
+
+enum UpvalueState {
+    Open(usize),
+    Closed(Object),
+}
+
+pub struct Closure {
+    proto: Rc<FunctionProto>,
+    upvalues: Rc<[Rc<Upvalue>]>,
+    /// `this` of a bound method, or the class of a static method
+    receiver: Option<Object>,
+}
+
+impl Closure {
+    fn with_receiver(&self, receiver: Object) -> Object {
+        Object::Callable(Rc::new(Closure {
+            proto: self.proto.clone(),
+            upvalues: self.upvalues.clone(),
+            receiver: Some(receiver),
+        }))
+    }
+}
+
+impl Callable for Closure {
+    fn arity(&self) -> Arity {
+        self.proto.arity()
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: &[Object]) -> Object {
+        self.call_with_named(interpreter, args, &[])
+    }
+
+    fn call_with_named(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &[Object],
+        named: &[(Identifier, Object)],
+    ) -> Object {
+        check_arity(self.arity(), args.len() + named.len());
+        let vm = &mut interpreter.vm;
+        let stop = vm.frames.len();
+        vm.stack.push(Object::Null);
+        vm.stack.extend(args.iter().cloned());
+        vm.stack
+            .extend(named.iter().map(|(_, value)| value.clone()));
+        let names: Vec<_> = named.iter().map(|(name, _)| name.clone()).collect();
+        vm.push_frame(self, args.len(), &names, false);
+        run(interpreter, stop)
+    }
+
+    fn name(&self) -> Option<&Identifier> {
+        Some(&self.proto.name)
+    }
+
+    fn bind(&self, instance: &Rc<ClassInstance>) -> Object {
+        self.with_receiver(Object::ClassInstance(instance.clone()))
+    }
+
+    fn bind_class(&self, class: Rc<Class>) -> Object {
+        self.with_receiver(Object::Class(class))
+    }
+
+    fn as_closure(&self) -> Option<&Closure> {
+        Some(self)
+    }
+}
+
+/// A generator call suspended by the `vm` backend
+struct Suspended {
+    frame: CallFrame,
+    slots: Vec<Object>,
+    /// Upvalues that pointed into the slots, by offset, they're closed while
+    /// the call is suspended and reopened when it resumes
+    open: Vec<(Rc<Upvalue>, usize)>,
+    iterators: Vec<Iter>,
+}
+
+struct VmCoroutine {
+    /// `None` once the body finished
+    suspended: Option<Suspended>,
+}
+
+impl Coroutine for VmCoroutine {
+    fn resume(&mut self, interpreter: &mut Interpreter) -> Option<Object> {
+        let suspended = self.suspended.take()?;
+        let vm = &mut interpreter.vm;
+        let stop = vm.frames.len();
+        let base = vm.stack.len();
+        vm.stack.extend(suspended.slots);
+        for (upvalue, offset) in suspended.open {
+            let slot = base + offset;
+            let state = std::mem::replace(&mut *upvalue.0.borrow_mut(), UpvalueState::Open(slot));
+            if let UpvalueState::Closed(value) = state {
+                vm.stack[slot] = value;
+            }
+            vm.open_upvalues.push(upvalue);
+        }
+        let iter_base = vm.iterators.len();
+        vm.iterators.extend(suspended.iterators);
+        vm.check_frames();
+        vm.frames.push(CallFrame {
+            base,
+            iter_base,
+            ..suspended.frame
+        });
+
+        let value = run(interpreter, stop);
+        let suspended = interpreter.vm.suspended.take();
+        let value = suspended.is_some().then_some(value);
+        self.suspended = suspended;
+        value
+    }
+}
+
+/// Runs a compiled script, returning the status it passed to `exit()` or 0
+pub fn interpret(interpreter: &mut Interpreter, script: Rc<FunctionProto>) -> i32 {
+    let script = Closure {
+        proto: script,
+        upvalues: Rc::new([]),
+        receiver: None,
+    };
+    interpreter.catch_exit(|interpreter| {
+        script.call(interpreter, &[]);
+    })
+}
+
+/// Fails on a count or slot reaching past the stack of the current frame,
+/// which only bytecode loaded from a forged or corrupt `.loxc` file has
+fn corrupt(op: &str, operand: usize) -> ! {
+    panic!("corrupt bytecode: {op} {operand} is out of range of the stack")
+}
+
+fn check_arity(arity: Arity, n_args: usize) {
+    if !arity.accepts(n_args) {
+        panic!("called fn/{arity} with {n_args}");
+    }
+}
+
+/// How far each of the VM's stacks reaches, see [`Vm::unwind`]
+pub(crate) struct Depth {
+    stack: usize,
+    frames: usize,
+    iterators: usize,
+}
+
+impl Vm {
+    pub(crate) fn depth(&self) -> Depth {
+        Depth {
+            stack: self.stack.len(),
+            frames: self.frames.len(),
+            iterators: self.iterators.len(),
+        }
+    }
+
+    /// Drops what the calls an `exit()` unwound through left past `depth`,
+    /// closing the upvalues of their locals
+    pub(crate) fn unwind(&mut self, depth: Depth) {
+        self.close_upvalues(depth.stack);
+        self.stack.truncate(depth.stack);
+        self.frames.truncate(depth.frames);
+        self.iterators.truncate(depth.iterators);
+        self.suspended = None;
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("bug: stack underflow")
+    }
+
+    /// The index of the first of the `n` values an instruction takes from
+    /// the top of the stack. Only corrupt bytecode takes more than the
+    /// current frame has.
+    fn top_start(&self, op: &str, n: usize) -> usize {
+        let base = self.frames.last().map_or(0, |frame| frame.base);
+        (self.stack.len().checked_sub(n))
+            .filter(|&start| start >= base)
+            .unwrap_or_else(|| corrupt(op, n))
+    }
+
+    fn peek(&self, depth: usize) -> &Object {
+        &self.stack[self.stack.len() - 1 - depth]
+    }
+
+    /// Starts a call to `closure`, whose arguments are on the stack above
+    /// the callee. The names are those of the last arguments.
+    /// Fails like any runtime error when one more frame would be too many
+    fn check_frames(&self) {
+        if self.frames.len() >= FRAMES_MAX {
+            let frame = self.frames.last().expect("bug: no frames");
+            // The scanner counts lines from 0
+            let line = frame.proto.chunk.line(frame.ip.saturating_sub(1)) + 1;
+            panic!("[line {line}] stack overflow");
+        }
+    }
+
+    fn push_frame(
+        &mut self,
+        closure: &Closure,
+        n_args: usize,
+        names: &[Identifier],
+        constructs: bool,
+    ) {
+        let proto = &closure.proto;
+        let base = self.stack.len() - n_args - names.len() - 1;
+        if let Some(receiver) = &closure.receiver {
+            self.stack[base] = receiver.clone();
+        }
+        let given = if names.is_empty() && n_args == proto.parameters.len() && !proto.rest {
+            u64::MAX
+        } else {
+            self.arrange_args(proto, base, n_args, names)
+        };
+        self.check_frames();
+        self.frames.push(CallFrame {
+            proto: proto.clone(),
+            upvalues: closure.upvalues.clone(),
+            ip: 0,
+            base,
+            iter_base: self.iterators.len(),
+            given,
+            constructs,
+        });
+    }
+
+    /// Puts the arguments in the parameters' slots, leaving nil for missing
+    /// ones with a default, and collects the rest. Returns which were given.
+    fn arrange_args(
+        &mut self,
+        proto: &FunctionProto,
+        base: usize,
+        n_args: usize,
+        names: &[Identifier],
+    ) -> u64 {
+        let named = self.stack.split_off(base + 1 + n_args);
+        let mut positional = self.stack.split_off(base + 1);
+        for name in names {
+            if !proto.parameters.iter().any(|p| &p.name == name) {
+                panic!("{} has no parameter named '{name}'", proto.name);
+            }
+        }
+        let rest = positional.split_off(n_args.min(proto.parameters.len()));
+
+        let mut given = 0;
+        for (i, param) in proto.parameters.iter().enumerate() {
+            let named_value = names
+                .iter()
+                .position(|name| name == &param.name)
+                .map(|j| named[j].clone());
+            let value = match (positional.get(i).cloned(), named_value) {
+                (Some(_), Some(_)) => {
+                    panic!(
+                        "argument '{}' given both by position and by name",
+                        param.name
+                    )
+                }
+                (Some(value), None) | (None, Some(value)) => {
+                    given |= 1 << i;
+                    value
+                }
+                (None, None) if param.has_default => Object::Null,
+                (None, None) => panic!(
+                    "missing argument '{}' in call to {}",
+                    param.name, proto.name
+                ),
+            };
+            self.stack.push(value);
+        }
+        if proto.rest {
+            self.stack.push(Object::new_list(rest));
+        }
+        given
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<Upvalue> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.0.borrow(), UpvalueState::Open(s) if s == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(Upvalue(RefCell::new(UpvalueState::Open(slot))));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Replaces the current frame by a call to the closure under the `n_args`
+    /// arguments on top of the stack. Returns false if the callee isn't a
+    /// closure, it's called the usual way then.
+    fn tail_call(&mut self, n_args: usize) -> bool {
+        let callee = self.top_start("TailCall", n_args + 1);
+        let Object::Callable(callable) = self.stack[callee].clone() else {
+            return false;
+        };
+        let Some(closure) = callable.as_closure() else {
+            return false;
+        };
+        check_arity(callable.arity(), n_args);
+
+        let frame = self.frames.pop().expect("bug: no frame");
+        self.close_upvalues(frame.base);
+        self.iterators.truncate(frame.iter_base);
+        self.stack.drain(frame.base..callee);
+        self.push_frame(closure, n_args, &[], false);
+        true
+    }
+
+    /// Moves the values of the slots from `from` on into the upvalues capturing them
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut state = upvalue.0.borrow_mut();
+            match *state {
+                UpvalueState::Open(slot) if slot >= from => {
+                    *state = UpvalueState::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn get_upvalue(&self, upvalue: &Upvalue) -> Object {
+        match &*upvalue.0.borrow() {
+            UpvalueState::Open(slot) => self.stack[*slot].clone(),
+            UpvalueState::Closed(value) => value.clone(),
+        }
+    }
+
+    fn set_upvalue(&mut self, upvalue: &Upvalue, value: Object) {
+        match &mut *upvalue.0.borrow_mut() {
+            UpvalueState::Open(slot) => self.stack[*slot] = value,
+            UpvalueState::Closed(closed) => *closed = value,
+        }
+    }
+
+    /// Prints the stack and the instruction at `ip` to stderr
+    fn trace(&self, chunk: &Chunk, ip: usize) {
+        let stack: String = self.stack.iter().map(|v| format!("[ {v:?} ]")).collect();
+        let mut instruction = String::new();
+        disassemble_instruction(chunk, ip, &mut instruction);
+        eprint!("          {stack}\n{instruction}");
+    }
+
+    /// Takes the frame off the stack so it can be resumed at `ip` later
+    fn suspend(&mut self, frame: CallFrame, ip: usize) -> Suspended {
+        let mut open = Vec::new();
+        self.open_upvalues
+            .retain(|upvalue| match *upvalue.0.borrow() {
+                UpvalueState::Open(slot) if slot >= frame.base => {
+                    open.push((upvalue.clone(), slot - frame.base));
+                    false
+                }
+                _ => true,
+            });
+        let slots = self.stack.split_off(frame.base);
+        for (upvalue, offset) in &open {
+            *upvalue.0.borrow_mut() = UpvalueState::Closed(slots[*offset].clone());
+        }
+        let iterators = self.iterators.split_off(frame.iter_base);
+        Suspended {
+            frame: CallFrame { ip, ..frame },
+            slots,
+            open,
+            iterators,
+        }
+    }
+}
+
+/// Calls the value below the arguments on top of the stack. Closures get a
+/// new frame, anything else runs right away and leaves its result.
+fn call_value(interpreter: &mut Interpreter, n_args: usize, names: &[Identifier]) {
+    let vm = &mut interpreter.vm;
+    let base = vm.top_start("Call", n_args + names.len() + 1);
+    let callee = vm.stack[base].clone();
+    let arity = match &callee {
+        Object::Callable(callable) => callable.arity(),
+        Object::Class(class) => class.arity(),
+        _ => panic!("'{callee}' is not callable"),
+    };
+    check_arity(arity, n_args + names.len());
+
+    if let Object::Callable(callable) = &callee {
+        if let Some(closure) = callable.as_closure() {
+            vm.push_frame(closure, n_args, names, false);
+            return;
+        }
+    }
+    if let Object::Class(class) = &callee {
+        let instance = Rc::new(ClassInstance::new(class.clone()));
+        match class.find_method(&Identifier::INIT) {
+            Some(Object::Callable(initializer)) if initializer.as_closure().is_some() => {
+                vm.stack[base] = Object::ClassInstance(instance);
+                let closure = initializer.as_closure().unwrap();
+                vm.push_frame(closure, n_args, names, true);
+                return;
+            }
+            None => {
+                vm.stack.truncate(base);
+                vm.stack.push(Object::ClassInstance(instance));
+                return;
+            }
+            Some(_) => {}
+        }
+    }
+
+    let named_values = vm.stack.split_off(base + 1 + n_args);
+    let args = vm.stack.split_off(base + 1);
+    vm.stack.pop();
+    let named: Vec<_> = names.iter().cloned().zip(named_values).collect();
+    let result = match callee {
+        Object::Class(class) => Class::instantiate(&class, interpreter, &args, &named),
+        Object::Callable(callable) => callable.call_with_named(interpreter, &args, &named),
+        _ => unreachable!(),
+    };
+    interpreter.vm.stack.push(result);
+}
+
+fn method_tables<'a>(
+    methods: &'a mut HashMap<Identifier, FunctionRef>,
+    getters: &'a mut HashMap<Identifier, FunctionRef>,
+    setters: &'a mut HashMap<Identifier, FunctionRef>,
+    kind: FunctionKind,
+) -> &'a mut HashMap<Identifier, FunctionRef> {
+    match kind {
+        FunctionKind::Function => methods,
+        FunctionKind::Getter => getters,
+        FunctionKind::Setter => setters,
+    }
+}
+
+fn function(value: Object) -> FunctionRef {
+    match value {
+        Object::Callable(function) => function,
+        other => panic!("bug: expected a method, got '{other:?}'"),
+    }
+}
+
+/// Runs frames until the one below the current one at `stop` frames returns,
+/// or a generator yields, and returns the value
+fn run(interpreter: &mut Interpreter, stop: usize) -> Object {
+    'frames: loop {
+        let frame = interpreter.vm.frames.last().expect("bug: no frame to run");
+        let proto = frame.proto.clone();
+        let upvalues = frame.upvalues.clone();
+        let base = frame.base;
+        let mut ip = frame.ip;
+        let chunk = &proto.chunk;
+        let code = &chunk.code[..];
+        let trace = interpreter.vm.trace;
+
+        macro_rules! read_u8 {
+            () => {{
+                ip += 1;
+                code[ip - 1]
+            }};
+        }
+        macro_rules! read_u16 {
+            () => {{
+                ip += 2;
+                u16::from_be_bytes([code[ip - 2], code[ip - 1]])
+            }};
+        }
+        macro_rules! constant {
+            ($variant:ident) => {{
+                let Constant::$variant(constant) = &chunk.constants[read_u16!() as usize] else {
+                    panic!("bug: expected a {} constant", stringify!($variant));
+                };
+                constant
+            }};
+        }
+        // Ends the current frame, returning `value` to its caller
+        macro_rules! finish {
+            ($value:expr) => {{
+                let value = $value;
+                let vm = &mut interpreter.vm;
+                if vm.frames.len() == stop {
+                    return value;
+                }
+                vm.stack.push(value);
+                continue 'frames;
+            }};
+        }
+
+        loop {
+            if trace {
+                interpreter.vm.trace(chunk, ip);
+            }
+            let op = OpCode::from_byte(read_u8!()).expect("bug: invalid opcode");
+            let vm = &mut interpreter.vm;
+            match op {
+                OpCode::Constant => {
+                    let value = match &chunk.constants[read_u16!() as usize] {
+                        Constant::Number(n) => Object::Number(*n),
+                        Constant::String(s) => Object::String(s.clone()),
+                        other => panic!("bug: '{other:?}' isn't a value"),
+                    };
+                    vm.stack.push(value);
+                }
+                OpCode::Nil => vm.stack.push(Object::Null),
+                OpCode::True => vm.stack.push(Object::Boolean(true)),
+                OpCode::False => vm.stack.push(Object::Boolean(false)),
+                OpCode::Pop => {
+                    vm.pop();
+                }
+                OpCode::Dup => vm.stack.push(vm.peek(0).clone()),
+                OpCode::Peek => {
+                    let depth = read_u8!() as usize;
+                    let slot = vm.top_start("Peek", depth + 1);
+                    vm.stack.push(vm.stack[slot].clone());
+                }
+                OpCode::Squash => {
+                    let n = read_u8!() as usize;
+                    let top = vm.pop();
+                    let len = vm.top_start("Squash", n);
+                    vm.close_upvalues(len);
+                    vm.stack.truncate(len);
+                    vm.stack.push(top);
+                }
+                OpCode::GetLocal => {
+                    let slot = read_u8!() as usize;
+                    let Some(value) = vm.stack.get(base + slot) else {
+                        corrupt("GetLocal", slot)
+                    };
+                    vm.stack.push(value.clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = read_u8!() as usize;
+                    let value = vm.peek(0).clone();
+                    let Some(local) = vm.stack.get_mut(base + slot) else {
+                        corrupt("SetLocal", slot)
+                    };
+                    *local = value;
+                }
+                OpCode::GetUpvalue => {
+                    let value = vm.get_upvalue(&upvalues[read_u8!() as usize]);
+                    vm.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let value = vm.peek(0).clone();
+                    vm.set_upvalue(&upvalues[read_u8!() as usize], value);
+                }
+                OpCode::CloseUpvalue => {
+                    vm.close_upvalues(vm.stack.len() - 1);
+                    vm.pop();
+                }
+                OpCode::GetGlobal => {
+                    let value = interpreter.get_global(constant!(Name));
+                    interpreter.vm.stack.push(value);
+                }
+                OpCode::SetGlobal => {
+                    let value = vm.peek(0).clone();
+                    interpreter.assign_global(constant!(Name), value);
+                }
+                OpCode::DefineGlobal => {
+                    let value = vm.pop();
+                    interpreter.define_global(constant!(Name).clone(), value);
+                }
+                OpCode::GetProperty => {
+                    let object = vm.pop();
+                    let value = interpreter.get_property(object, constant!(Name));
+                    interpreter.vm.stack.push(value);
+                }
+                OpCode::SetProperty => {
+                    let value = vm.pop();
+                    let object = vm.pop();
+                    interpreter.set_property(object, constant!(Name), value.clone());
+                    interpreter.vm.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let superclass = vm.pop();
+                    let object = vm.pop();
+                    let method = interpreter.get_super(superclass, object, constant!(Name));
+                    interpreter.vm.stack.push(method);
+                }
+                OpCode::GetIndex => {
+                    let index = vm.pop();
+                    let object = vm.pop();
+                    let value = interpreter.get_index(object, index);
+                    interpreter.vm.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = vm.pop();
+                    let index = vm.pop();
+                    let object = vm.pop();
+                    interpreter.set_index(object, index, value.clone());
+                    interpreter.vm.stack.push(value);
+                }
+                OpCode::Equal | OpCode::NotEqual => {
+                    let right = vm.pop();
+                    let left = vm.pop();
+                    let equal = match (&left, &right) {
+                        (Object::Number(left), Object::Number(right)) => left == right,
+                        _ => interpreter.is_equal(&left, &right),
+                    };
+                    let result = if op == OpCode::Equal { equal } else { !equal };
+                    interpreter.vm.stack.push(Object::Boolean(result));
+                }
+                OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide => {
+                    let right = vm.pop();
+                    let left = vm.pop();
+                    let result = match (op, &left, &right) {
+                        (OpCode::Add, Object::Number(a), Object::Number(b)) => {
+                            Object::Number(a + b)
+                        }
+                        (OpCode::Subtract, Object::Number(a), Object::Number(b)) => {
+                            Object::Number(a - b)
+                        }
+                        (OpCode::Multiply, Object::Number(a), Object::Number(b)) => {
+                            Object::Number(a * b)
+                        }
+                        (OpCode::Divide, Object::Number(a), Object::Number(b)) => {
+                            Object::Number(a / b)
+                        }
+                        (OpCode::Less, Object::Number(a), Object::Number(b)) => {
+                            Object::Boolean(a < b)
+                        }
+                        (OpCode::LessEqual, Object::Number(a), Object::Number(b)) => {
+                            Object::Boolean(a <= b)
+                        }
+                        (OpCode::Greater, Object::Number(a), Object::Number(b)) => {
+                            Object::Boolean(a > b)
+                        }
+                        (OpCode::GreaterEqual, Object::Number(a), Object::Number(b)) => {
+                            Object::Boolean(a >= b)
+                        }
+                        _ => {
+                            let op = match op {
+                                OpCode::Greater => TokenType::Greater,
+                                OpCode::GreaterEqual => TokenType::GreaterEqual,
+                                OpCode::Less => TokenType::Less,
+                                OpCode::LessEqual => TokenType::LessEqual,
+                                OpCode::Add => TokenType::Plus,
+                                OpCode::Subtract => TokenType::Minus,
+                                OpCode::Multiply => TokenType::Star,
+                                _ => TokenType::Slash,
+                            };
+                            interpreter.binary(left, op, right)
+                        }
+                    };
+                    interpreter.vm.stack.push(result);
+                }
+                OpCode::Negate => {
+                    let value = match vm.pop() {
+                        Object::Number(n) => Object::Number(-n),
+                        value => interpreter.unary(TokenType::Minus, value),
+                    };
+                    interpreter.vm.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value = vm.pop();
+                    let value = interpreter.unary(TokenType::Bang, value);
+                    interpreter.vm.stack.push(value);
+                }
+                OpCode::Print => {
+                    let value = vm.pop();
+                    println!("{}", interpreter.stringify(&value));
+                }
+                OpCode::Jump => {
+                    let offset = read_u16!() as usize;
+                    ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = read_u16!() as usize;
+                    match vm.pop() {
+                        Object::Boolean(true) => {}
+                        Object::Boolean(false) => ip += offset,
+                        other => panic!("condition can only be boolean, got '{other:?}'"),
+                    }
+                }
+                OpCode::JumpIfFalsy => {
+                    let offset = read_u16!() as usize;
+                    if !vm.peek(0).is_truthy() {
+                        ip += offset;
+                    }
+                }
+                OpCode::JumpIfTruthy => {
+                    let offset = read_u16!() as usize;
+                    if vm.peek(0).is_truthy() {
+                        ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = read_u16!() as usize;
+                    ip -= offset;
+                }
+                OpCode::JumpIfArg => {
+                    let param = read_u8!();
+                    let offset = read_u16!() as usize;
+                    let frame = vm.frames.last().expect("bug: no frame");
+                    if frame.given & (1 << param) != 0 {
+                        ip += offset;
+                    }
+                }
+                OpCode::Call | OpCode::CallNamed | OpCode::TailCall => {
+                    let n_args = read_u8!() as usize;
+                    let names = match op {
+                        OpCode::CallNamed => &constant!(Names)[..],
+                        _ => &[],
+                    };
+                    if op == OpCode::TailCall && vm.tail_call(n_args) {
+                        continue 'frames;
+                    }
+                    vm.frames.last_mut().expect("bug: no frame").ip = ip;
+                    let depth = vm.frames.len();
+                    call_value(interpreter, n_args, names);
+                    if interpreter.vm.frames.len() > depth {
+                        continue 'frames;
+                    }
+                }
+                OpCode::Closure => {
+                    let function = constant!(Function);
+                    let captured = function
+                        .upvalues
+                        .iter()
+                        .map(|upvalue| {
+                            if upvalue.is_local {
+                                vm.capture_upvalue(base + upvalue.index as usize)
+                            } else {
+                                upvalues[upvalue.index as usize].clone()
+                            }
+                        })
+                        .collect();
+                    vm.stack.push(Object::Callable(Rc::new(Closure {
+                        proto: function.clone(),
+                        upvalues: captured,
+                        receiver: None,
+                    })));
+                }
+                OpCode::Return => {
+                    let mut value = vm.pop();
+                    let frame = vm.frames.pop().expect("bug: no frame");
+                    if frame.constructs {
+                        value = vm.stack[frame.base].clone();
+                    }
+                    vm.close_upvalues(frame.base);
+                    vm.stack.truncate(frame.base);
+                    vm.iterators.truncate(frame.iter_base);
+                    finish!(value);
+                }
+                OpCode::Class => {
+                    let class = constant!(Class);
+                    let n_values = class.has_superclass as usize
+                        + class.traits as usize
+                        + class.methods.len()
+                        + class.static_methods.len();
+                    let values = vm.stack.split_off(vm.top_start("Class", n_values));
+                    let mut values = values.into_iter();
+
+                    let superclass = class.has_superclass.then(|| match values.next() {
+                        Some(Object::Class(superclass)) => superclass,
+                        _ => panic!("superclass is not a class!"),
+                    });
+                    // Trait methods are copied into the class, its own methods take precedence
+                    let mut mixins = Mixins::default();
+                    for _ in 0..class.traits {
+                        let Some(Object::Trait(mixin)) = values.next() else {
+                            panic!("'{}' is not a trait!", class.name);
+                        };
+                        mixins.add(&class.name, &mixin, |name, kind| {
+                            (class.methods.iter()).any(|(n, k)| n == name && *k == kind)
+                        });
+                    }
+                    let Mixins {
+                        mut methods,
+                        mut getters,
+                        mut setters,
+                        ..
+                    } = mixins;
+                    for (name, kind) in &class.methods {
+                        let function = function(values.next().expect("bug: missing method"));
+                        method_tables(&mut methods, &mut getters, &mut setters, *kind)
+                            .insert(name.clone(), function);
+                    }
+                    let static_methods = class
+                        .static_methods
+                        .iter()
+                        .map(|name| {
+                            let function = function(values.next().expect("bug: missing method"));
+                            (name.clone(), function)
+                        })
+                        .collect();
+
+                    vm.stack.push(Object::Class(Rc::new(Class::new(
+                        class.name.clone(),
+                        superclass,
+                        methods,
+                        getters,
+                        setters,
+                        static_methods,
+                    ))));
+                }
+                OpCode::Trait => {
+                    let proto = constant!(Trait);
+                    let values = (vm.stack).split_off(vm.top_start("Trait", proto.methods.len()));
+                    let mut mixin = Trait::new(proto.name.clone());
+                    for ((name, kind), value) in proto.methods.iter().zip(values) {
+                        method_tables(
+                            &mut mixin.methods,
+                            &mut mixin.getters,
+                            &mut mixin.setters,
+                            *kind,
+                        )
+                        .insert(name.clone(), function(value));
+                    }
+                    vm.stack.push(Object::Trait(Rc::new(mixin)));
+                }
+                OpCode::Enum => {
+                    let proto = constant!(Enum);
+                    let variants = proto
+                        .variants
+                        .iter()
+                        .map(|(name, fields)| Variant {
+                            name: name.clone(),
+                            fields: fields.clone(),
+                        })
+                        .collect();
+                    let enumeration = Enum::new(proto.name.clone(), variants);
+                    vm.stack.push(Object::Enum(Rc::new(enumeration)));
+                }
+                OpCode::List => {
+                    let n_items = read_u16!() as usize;
+                    let items = vm.stack.split_off(vm.top_start("List", n_items));
+                    vm.stack.push(Object::new_list(items));
+                }
+                OpCode::Map => {
+                    let keys = constant!(Keys);
+                    let values = vm.stack.split_off(vm.top_start("Map", keys.len()));
+                    let map = keys.iter().cloned().zip(values).collect();
+                    vm.stack.push(Object::Map(Rc::new(RefCell::new(map))));
+                }
+                OpCode::Append => {
+                    let item = vm.pop();
+                    let Object::List(list) = vm.peek(0) else {
+                        panic!("bug: appending to a value that isn't a list");
+                    };
+                    list.borrow_mut().push(item);
+                }
+                OpCode::Extend => {
+                    let items = match vm.pop() {
+                        Object::List(items) => items,
+                        other => panic!("can only spread lists, got '{other:?}'"),
+                    };
+                    let Object::List(list) = vm.peek(0) else {
+                        panic!("bug: extending a value that isn't a list");
+                    };
+                    let items = items.borrow().clone();
+                    list.borrow_mut().extend(items);
+                }
+                OpCode::Iter => {
+                    let iterable = vm.pop();
+                    let iter = Iter::new(interpreter, iterable);
+                    interpreter.vm.iterators.push(iter);
+                }
+                OpCode::IterNext => {
+                    let offset = read_u16!() as usize;
+                    let mut iter = vm.iterators.pop().expect("bug: no iterator");
+                    let item = iter.next(interpreter);
+                    let vm = &mut interpreter.vm;
+                    vm.iterators.push(iter);
+                    match item {
+                        Some(item) => vm.stack.push(item),
+                        None => ip += offset,
+                    }
+                }
+                OpCode::IterEnd => {
+                    vm.iterators.pop();
+                }
+                OpCode::Generator => {
+                    let frame = vm.frames.pop().expect("bug: no frame");
+                    let suspended = vm.suspend(frame, ip);
+                    let coroutine = VmCoroutine {
+                        suspended: Some(suspended),
+                    };
+                    let generator = Generator::new(proto.name.clone(), Box::new(coroutine));
+                    finish!(Object::Generator(Rc::new(generator)));
+                }
+                OpCode::Yield => {
+                    let value = vm.pop();
+                    let frame = vm.frames.pop().expect("bug: no frame");
+                    vm.suspended = Some(vm.suspend(frame, ip));
+                    assert_eq!(vm.frames.len(), stop, "bug: yield from a nested frame");
+                    return value;
+                }
+                OpCode::Match => {
+                    let pattern = constant!(Pattern);
+                    let offset = read_u16!() as usize;
+                    let enumeration = vm.pop();
+                    let value = vm.peek(0).clone();
+                    match interpreter.pattern_bindings(pattern, &enumeration, &value) {
+                        Some(bindings) => interpreter.vm.stack.extend(bindings),
+                        None => ip += offset,
+                    }
+                }
+                OpCode::NoMatch => {
+                    let value = vm.peek(0);
+                    panic!("no match arm matched value {value}");
+                }
+                OpCode::CheckList => {
+                    let n_items = read_u8!() as usize;
+                    let has_rest = read_u8!() != 0;
+                    destructure_list(vm.peek(0), n_items, has_rest);
+                }
+                OpCode::RestList => {
+                    let n_items = read_u8!() as usize;
+                    let Object::List(list) = vm.pop() else {
+                        panic!("bug: rest of a value that isn't a list");
+                    };
+                    let rest = (list.borrow().get(n_items..))
+                        .unwrap_or_else(|| corrupt("RestList", n_items))
+                        .to_vec();
+                    vm.stack.push(Object::new_list(rest));
+                }
+                OpCode::Field => {
+                    let value = vm.pop();
+                    let field = interpreter.destructure_field(&value, constant!(Name));
+                    interpreter.vm.stack.push(field);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "[line 3] stack overflow")]
+fn test_stack_overflow() {
+    let (ast, mut interpreter) = crate::resolver::resolve_source(
+        "
+        fun down(n) {
+          return down(n + 1) + 1;
+        }
+        down(0);
+        ",
+    );
+    interpret(&mut interpreter, crate::compiler::compile(&ast));
+}
+
+#[test]
+fn test_exit_unwinds_the_stacks() {
+    let (ast, mut interpreter) = crate::resolver::resolve_source(
+        "
+        var get = nil;
+        fun stop(items) {
+          for (var item in items) {
+            exit(item);
+          }
+        }
+        {
+          var captured = 1;
+          fun f() { return captured; }
+          get = f;
+          stop([4]);
+        }
+        ",
+    );
+    assert_eq!(
+        interpret(&mut interpreter, crate::compiler::compile(&ast)),
+        4
+    );
+    let vm = &interpreter.vm;
+    assert!(vm.stack.is_empty() && vm.frames.is_empty() && vm.iterators.is_empty());
+    // Closures keep the locals they captured from the calls that unwound
+    let Object::Callable(get) = interpreter.get_global(&interpreter.interner().intern("get"))
+    else {
+        panic!("get isn't a function");
+    };
+    assert_eq!(get.call(&mut interpreter, &[]), Object::Number(1.0));
+}
@@ -0,0 +1,41 @@
+//! Runs `lox` as a process, for behaviour only visible from outside it
+
+use std::{fs, path::PathBuf, process::Command};
+
+/// Writes `source` to a script in the test's temporary directory
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn test_exit_status() {
+    let script = script("exit_status.lox", "print 1;\nexit(3);\nprint 2;\n");
+    for backend in ["tree", "vm"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .args(["--backend", backend])
+            .arg(&script)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(3), "on {backend}");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "1\n",
+            "on {backend}"
+        );
+    }
+}
+
+#[test]
+fn test_trace_needs_the_vm() {
+    let script = script("trace.lox", "print 1;\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(["--trace", "--backend", "tree"])
+        .arg(&script)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--trace can't be used with --backend tree"));
+}
@@ -0,0 +1,91 @@
+//! Runs every script in `examples/` on each backend and compares its output
+//! with the matching file in `tests/golden/`. Set `LOX_BLESS=1` to rewrite
+//! the expected outputs after an intended change.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Makes `clock()` and `random()` reproducible
+const HOST_ARGS: &[&str] = &["--seed", "42", "--fixed-clock", "0"];
+
+/// Set for the scripts, `examples/process.lox` reads it
+const ENV: (&str, &str) = ("LOX_EXAMPLE", "golden");
+
+/// `loxc` compiles the script with `lox compile` and runs the result with `lox run`
+const BACKENDS: &[&str] = &["tree", "vm", "loxc"];
+
+fn examples() -> Vec<PathBuf> {
+    let mut scripts: Vec<_> = fs::read_dir("examples")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+fn run(script: &Path, backend: &str) -> String {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_lox"));
+    if backend == "loxc" {
+        let name = script.file_stem().unwrap().to_string_lossy();
+        let compiled = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.loxc"));
+        let status = Command::new(env!("CARGO_BIN_EXE_lox"))
+            .arg("compile")
+            .arg(script)
+            .arg("-o")
+            .arg(&compiled)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{} failed to compile", script.display());
+        command.arg("run").args(HOST_ARGS).arg(compiled);
+    } else {
+        command
+            .args(HOST_ARGS)
+            .args(["--backend", backend])
+            .arg(script);
+    }
+    let output = command
+        .env(ENV.0, ENV.1)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{} failed on {backend}:\n{}",
+        script.display(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_golden() {
+    let bless = std::env::var_os("LOX_BLESS").is_some();
+    let mut failures = Vec::new();
+    for script in examples() {
+        let name = script.file_stem().unwrap().to_string_lossy();
+        let expected_path = Path::new("tests/golden").join(format!("{name}.out"));
+        if bless {
+            fs::write(&expected_path, run(&script, BACKENDS[0])).unwrap();
+            continue;
+        }
+
+        let Ok(expected) = fs::read_to_string(&expected_path) else {
+            failures.push(format!("{} is missing", expected_path.display()));
+            continue;
+        };
+        for backend in BACKENDS {
+            let actual = run(&script, backend);
+            if expected != actual {
+                failures.push(format!(
+                    "{} on {backend}: expected\n{expected}\ngot\n{actual}",
+                    script.display()
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}